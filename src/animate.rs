@@ -1,7 +1,15 @@
+use bevy::asset::{AssetLoader, LoadContext, LoadedAsset};
 use bevy::prelude::*;
+use bevy::reflect::{TypePath, TypeUuid};
+use bevy::sprite::Anchor;
 use bevy::time::Time;
+use bevy::utils::{BoxedFuture, HashMap};
+use serde::Deserialize;
 
-use crate::player::{Player, StateChangeEvent};
+use crate::player::Player;
+
+//没有填写Delay时每帧的时间,毫秒
+const DEFAULT_DELAY: u32 = 100;
 
 #[derive(Component, Clone, Default, Debug)]
 pub struct AnimationTimer(pub Timer);
@@ -12,94 +20,282 @@ pub struct AnimationIndices {
     pub sprite_indices: Vec<usize>,
 }
 
+//播放到最后一帧之后的处理
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+pub enum LoopMode {
+    //回到第一帧
+    #[default]
+    Loop,
+    //倒着播放回第一帧,例如stand和alert
+    PingPong,
+    //停在最后一帧
+    Once,
+}
+
 #[derive(Clone, Debug, Default, Component)]
 pub struct Animation {
     pub timer: AnimationTimer,
     pub indices: AnimationIndices,
     pub name: String,
+    //每一帧的时间,秒,和sprite_indices一一对应
+    pub delays: Vec<f32>,
+    pub mode: LoopMode,
+    //PingPong时是否正向播放
+    pub forward: bool,
+    //每一帧原点对应的锚点,未翻转时的值
+    pub anchors: Vec<Vec2>,
 }
 
-//
-#[derive(Component)]
-pub struct Animations {
-    pub sprite: Vec<SpriteBundle>,
-    pub index: i32,
-    pub delays: Vec<f32>,
+impl Animation {
+    pub fn new(
+        name: &str,
+        sprite_indices: Vec<usize>,
+        delays: Vec<f32>,
+        anchors: Vec<Vec2>,
+        mode: LoopMode,
+    ) -> Animation {
+        let first = delays.first().copied().unwrap_or(DEFAULT_DELAY as f32 / 1000.0);
+        Animation {
+            timer: AnimationTimer(Timer::from_seconds(first, TimerMode::Once)),
+            indices: AnimationIndices {
+                index: 0,
+                sprite_indices,
+            },
+            name: name.to_string(),
+            delays,
+            mode,
+            forward: true,
+            anchors,
+        }
+    }
+
+    //切换到下一帧,返回帧序号是否改变
+    pub fn advance(&mut self) -> bool {
+        let len = self.indices.sprite_indices.len();
+        let index = self.indices.index;
+        let next = match self.mode {
+            _ if len <= 1 => index,
+            LoopMode::Loop => (index + 1) % len,
+            LoopMode::Once => (index + 1).min(len - 1),
+            LoopMode::PingPong => {
+                if (self.forward && index + 1 == len) || (!self.forward && index == 0) {
+                    self.forward = !self.forward;
+                }
+                if self.forward { index + 1 } else { index - 1 }
+            }
+        };
+        self.indices.index = next;
+        self.timer = AnimationTimer(Timer::from_seconds(self.delays[next], TimerMode::Once));
+        next != index
+    }
+
+    pub fn sprite_index(&self) -> usize {
+        self.indices.sprite_indices[self.indices.index]
+    }
+
+    //图片左右翻转后原点也要翻转
+    pub fn anchor(&self, flip_x: bool) -> Anchor {
+        let anchor = self.anchors[self.indices.index];
+        if flip_x {
+            Anchor::Custom(Vec2::new(-anchor.x, anchor.y))
+        } else {
+            Anchor::Custom(anchor)
+        }
+    }
+}
+
+//动作中的一帧,Image相对于assets目录
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct FrameDef {
+    pub image: String,
+    #[serde(default = "FrameDef::default_delay")]
+    pub delay: u32,
+    //原点在图片中的位置,一般在脚下,没有时为底部中间
+    pub origin_x: Option<i32>,
+    pub origin_y: Option<i32>,
+}
+
+impl FrameDef {
+    fn default_delay() -> u32 {
+        DEFAULT_DELAY
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct AnimationDef {
+    #[serde(default)]
+    pub loop_mode: LoopMode,
+    pub frames: Vec<FrameDef>,
+}
+
+//人物的动作定义,从*.animation.json读取,动作名对应PlayerState::animation
+#[derive(Debug, Clone, Deserialize, TypeUuid, TypePath)]
+#[uuid = "5b2e7c90-1d4f-4a63-b8e2-7f0c3a9d6e14"]
+#[serde(rename_all = "PascalCase")]
+pub struct AnimationSet {
+    pub actions: HashMap<String, AnimationDef>,
+}
+
+#[derive(Default)]
+pub struct AnimationSetLoader;
+
+impl AssetLoader for AnimationSetLoader {
+    fn load<'a>(
+        &'a self,
+        bytes: &'a [u8],
+        load_context: &'a mut LoadContext,
+    ) -> BoxedFuture<'a, Result<(), bevy::asset::Error>> {
+        Box::pin(async move {
+            let set: AnimationSet = serde_json::from_slice(bytes)?;
+            load_context.set_default_asset(LoadedAsset::new(set));
+            Ok(())
+        })
+    }
+
+    fn extensions(&self) -> &[&str] {
+        &["animation.json"]
+    }
+}
+
+//obj动画的一帧
+#[derive(Clone, Debug)]
+pub struct AnimationFrame {
+    pub texture: Handle<Image>,
+    pub anchor: Vec2,
+    //秒
     pub delay: f32,
-    pub start: bool,
-    pub lastsprite: Option<Entity>,
+    //开始和结束时的透明度,0-255
+    pub alpha: (f32, f32),
+}
+
+//obj动画,同一个实体上切换图片和锚点
+#[derive(Component, Clone, Debug)]
+pub struct Animations {
+    pub frames: Vec<AnimationFrame>,
+    pub index: usize,
+    pub timer: Timer,
+}
+
+impl Animations {
+    pub fn new(frames: Vec<AnimationFrame>) -> Animations {
+        let delay = frames.first().map_or(0.0, |frame| frame.delay);
+        Animations {
+            frames,
+            index: 0,
+            timer: Timer::from_seconds(delay, TimerMode::Once),
+        }
+    }
 }
 
 pub struct AnimatePlugin;
 
 impl Plugin for AnimatePlugin {
     fn build(&self, app: &mut App) {
+        app.add_asset::<AnimationSet>()
+            .init_asset_loader::<AnimationSetLoader>();
         app.add_systems(Update,animate_player);
         app.add_systems(Update,animate_back);
     }
 }
 
 
-//播放人物行走动画
+//播放人物动画,每一帧按自己的delay切换
 fn animate_player(
-    mut commands: Commands,
-    mut q_player: Query<
-        (
-            Entity,
-            &mut Animation,
-            &mut TextureAtlasSprite,
-        ),
-        With<Player>,
-    >,
+    mut q_player: Query<(&mut Animation, &mut TextureAtlasSprite), With<Player>>,
     time: Res<Time>,
-    state_change_ev: EventReader<StateChangeEvent>,
 ) {
-    for (entity, mut animation, mut sprite) in &mut q_player {
-        // println!("{:?}",animation.name);
-        if !state_change_ev.is_empty() || animation.timer.0.tick(time.delta()).just_finished() {
-            let current_idx = animation
-                .indices.sprite_indices.iter()
-                .position(|s| *s == sprite.index)
-                .unwrap_or(0); // default to 0 if the current sprite is not in the set
-
-            let next_idx = (current_idx + animation.timer.0.times_finished_this_tick() as usize)
-                % animation.indices.sprite_indices.len();
-
-            sprite.index = animation.indices.sprite_indices[next_idx];
-            // state_change_ev.clear();
+    for (mut animation, mut sprite) in &mut q_player {
+        if animation.indices.sprite_indices.is_empty() {
+            continue;
+        }
+        if animation.timer.0.tick(time.delta()).just_finished() {
+            animation.advance();
+        }
+        //切换动作后新的Animation从第一帧开始
+        let index = animation.sprite_index();
+        if sprite.index != index {
+            sprite.index = index;
+        }
+        let anchor = animation.anchor(sprite.flip_x);
+        if sprite.anchor.as_vec() != anchor.as_vec() {
+            sprite.anchor = anchor;
         }
     }
 }
 
-//背景动画,背景obj的动画效果
-fn animate_back(time: Res<Time>, mut commands: Commands, mut query: Query<&mut Animations>) {
-    // println!("{:?}", time.raw_elapsed_seconds());
+//背景动画,背景obj的动画效果,每一帧的透明度从A0渐变到A1
+fn animate_back(
+    time: Res<Time>,
+    mut query: Query<(&mut Animations, &mut Sprite, &mut Handle<Image>)>,
+) {
+    for (mut animations, mut sprite, mut texture) in &mut query {
+        let len = animations.frames.len();
+        //单帧的obj也要计时,透明度渐变一次后保持
+        animations.timer.tick(time.delta());
+        if len > 1 && animations.timer.finished() {
+            //切换到下一帧
+            let index = (animations.index + 1) % len;
+            let frame = &animations.frames[index];
+            *texture = frame.texture.clone();
+            sprite.anchor = Anchor::Custom(frame.anchor);
+            let delay = frame.delay;
+            animations.index = index;
+            animations.timer = Timer::from_seconds(delay, TimerMode::Once);
+        }
 
-    for mut s in &mut query {
-        // println!("{:?}", s);
-        if s.index == -1 {
-            s.index += 1;
-            s.lastsprite = Some(commands.spawn(s.sprite[0].to_owned()).id());
-            s.delay = s.delays[s.index as usize] / 1000.0 + time.raw_elapsed_seconds();
-            s.start = true;
-        } else {
-            if s.lastsprite != None {
-                if s.start == true {
-                    if time.raw_elapsed_seconds() >= s.delay {
-                        commands.entity(s.lastsprite.unwrap()).despawn();
-                        s.lastsprite =
-                            Some(commands.spawn(s.sprite[s.index as usize].to_owned()).id());
-                        if s.index as usize == s.sprite.len() - 1 {
-                            s.index = 0;
-                        } else {
-                            s.index += 1;
-                        }
-                        s.delay = s.delays[s.index as usize] / 1000.0 + time.raw_elapsed_seconds();
-                    }
-                }
-            }
+        //不透明的帧也要设置,上一帧渐变后的透明度不能保留
+        let (a0, a1) = animations.frames[animations.index].alpha;
+        let t = if animations.timer.duration().is_zero() { 1.0 } else { animations.timer.percent() };
+        let alpha = (a0 + (a1 - a0) * t) / 255.0;
+        if sprite.color.a() != alpha {
+            sprite.color.set_a(alpha);
         }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::{Duration, Instant};
+
+    fn frame(delay: f32, alpha: (f32, f32)) -> AnimationFrame {
+        AnimationFrame {
+            texture: Handle::default(),
+            anchor: Vec2::ZERO,
+            delay,
+            alpha,
+        }
+    }
+
+    //渐隐的帧之后是不透明的帧,切换后要恢复不透明
+    #[test]
+    fn opaque_frame_after_fading_frame_is_visible() {
+        let mut app = App::new();
+        app.init_resource::<Time>().add_systems(Update, animate_back);
+        let obj = app
+            .world
+            .spawn((
+                Animations::new(vec![frame(1.0, (255.0, 0.0)), frame(1.0, (255.0, 255.0))]),
+                Sprite::default(),
+                Handle::<Image>::default(),
+            ))
+            .id();
+        let start = Instant::now();
+        let run = |app: &mut App, seconds: f32| {
+            let instant = start + Duration::from_secs_f32(seconds);
+            app.world.resource_mut::<Time>().update_with_instant(instant);
+            app.update();
+            let index = app.world.get::<Animations>(obj).unwrap().index;
+            (index, app.world.get::<Sprite>(obj).unwrap().color.a())
+        };
 
-        // println!("{:?}", s.sprite);
+        assert_eq!(run(&mut app, 0.0), (0, 1.0));
+        let (index, alpha) = run(&mut app, 0.9);
+        assert_eq!(index, 0);
+        assert!((alpha - 0.1).abs() < 1e-3);
+        assert_eq!(run(&mut app, 1.0), (1, 1.0));
+        assert_eq!(run(&mut app, 1.5), (1, 1.0));
     }
 }
@@ -0,0 +1,540 @@
+use std::{collections::BTreeMap, path::Path};
+
+use bevy::{
+    asset::{AssetLoader, LoadContext, LoadState, LoadedAsset},
+    prelude::*,
+    reflect::{TypePath, TypeUuid},
+    sprite::Anchor,
+    utils::{BoxedFuture, HashMap},
+};
+use serde::Deserialize;
+
+use crate::{
+    player::{Climb, Direction, Player, PlayerState, PlayerStateChanged, PLAYER_HALF_HEIGHT},
+    utils::{cal_ax, cal_ay},
+};
+
+//角色部件所在目录,不存在时人物使用整张图片
+const CHARACTER_DIR: &str = "assets/Character";
+//部件之间的z间隔,所有部件都要在人物的z之下一个单位之内
+const PART_Z_STEP: f32 = 0.0002;
+
+//部件的绘制顺序,从后往前,对应zmap.img的常用部分
+const Z_ORDER: &[&str] = &[
+    "hairBelowBody",
+    "capeBelowBody",
+    "shieldBelowBody",
+    "weaponBelowBody",
+    "body",
+    "shoes",
+    "pants",
+    "mailChest",
+    "head",
+    "face",
+    "hairShade",
+    "hair",
+    "cap",
+    "hairOverHead",
+    "capOverHair",
+    "weaponBelowArm",
+    "arm",
+    "mailArm",
+    "weapon",
+    "weaponOverArm",
+    "hand",
+    "weaponOverHand",
+];
+
+//角色部件,arm等身体上的图片属于Body
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum AvatarPart {
+    Body,
+    Head,
+    Face,
+    Hair,
+    Cap,
+    Coat,
+    Pants,
+    Shoes,
+    Weapon,
+}
+
+impl AvatarPart {
+    //按wz的物品id范围判断部件
+    pub fn from_id(id: i32) -> Option<AvatarPart> {
+        match id / 10000 {
+            0 => Some(AvatarPart::Body),
+            1 => Some(AvatarPart::Head),
+            2 => Some(AvatarPart::Face),
+            3 => Some(AvatarPart::Hair),
+            100 => Some(AvatarPart::Cap),
+            104 | 105 => Some(AvatarPart::Coat),
+            106 => Some(AvatarPart::Pants),
+            107 => Some(AvatarPart::Shoes),
+            130..=170 => Some(AvatarPart::Weapon),
+            _ => None,
+        }
+    }
+
+    //部件json的路径,相对于assets目录
+    pub fn path(self, id: i32) -> String {
+        let dir = match self {
+            AvatarPart::Body | AvatarPart::Head => "Character",
+            AvatarPart::Face => "Character/Face",
+            AvatarPart::Hair => "Character/Hair",
+            AvatarPart::Cap => "Character/Cap",
+            AvatarPart::Coat => "Character/Coat",
+            AvatarPart::Pants => "Character/Pants",
+            AvatarPart::Shoes => "Character/Shoes",
+            AvatarPart::Weapon => "Character/Weapon",
+        };
+        format!("{}/{:08}.part.json", dir, id)
+    }
+}
+
+//部件某一帧中的一张图片,map为挂点(navel,neck,hand,brow)相对原点的位置
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct PartSprite {
+    pub name: String,
+    pub width: i32,
+    pub height: i32,
+    pub origin_x: i32,
+    pub origin_y: i32,
+    pub z: String,
+    #[serde(default)]
+    pub map: HashMap<String, [f32; 2]>,
+    pub resource_url: String,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct PartFrame {
+    #[serde(default)]
+    pub delay: u32,
+    pub sprites: Vec<PartSprite>,
+}
+
+//一个部件的所有动作,脸和头发等没有动作的部件使用default,格式见README
+//{"Actions": {"stand1": [{"Delay": 500, "Sprites": [{"Name": "body", "Width": 21, "Height": 31,
+//  "OriginX": 16, "OriginY": 31, "Z": "body", "Map": {"navel": [-8, -21], "neck": [-4, -32]},
+//  "ResourceUrl": "Character/00002000.img/stand1/0/body.png"}]}]}}
+#[derive(Debug, Clone, Deserialize, TypeUuid, TypePath)]
+#[uuid = "3f6a0b2e-9c41-4d7e-8b15-6e2d9a7c4f08"]
+#[serde(rename_all = "PascalCase")]
+pub struct CharacterPart {
+    pub actions: HashMap<String, Vec<PartFrame>>,
+}
+
+impl CharacterPart {
+    pub fn frames(&self, action: &str) -> &[PartFrame] {
+        self.actions
+            .get(action)
+            .or_else(|| self.actions.get("default"))
+            .map_or(&[], |frames| frames.as_slice())
+    }
+
+    pub fn frame(&self, action: &str, index: usize) -> Option<&PartFrame> {
+        let frames = self.frames(action);
+        frames.get(index % frames.len().max(1))
+    }
+}
+
+#[derive(Default)]
+pub struct CharacterPartLoader;
+
+impl AssetLoader for CharacterPartLoader {
+    fn load<'a>(
+        &'a self,
+        bytes: &'a [u8],
+        load_context: &'a mut LoadContext,
+    ) -> BoxedFuture<'a, Result<(), bevy::asset::Error>> {
+        Box::pin(async move {
+            let part: CharacterPart = serde_json::from_slice(bytes)?;
+            load_context.set_default_asset(LoadedAsset::new(part));
+            Ok(())
+        })
+    }
+
+    fn extensions(&self) -> &[&str] {
+        &["part.json"]
+    }
+}
+
+//组合后的一张图片,position为原点相对人物脚下的位置
+#[derive(Debug, Clone, PartialEq)]
+pub struct PlacedSprite {
+    pub resource_url: String,
+    pub position: Vec2,
+    pub anchor: Vec2,
+    pub z: f32,
+}
+
+fn z_index(z: &str) -> usize {
+    Z_ORDER
+        .iter()
+        .position(|name| *name == z)
+        .or_else(|| Z_ORDER.iter().position(|name| *name == "body"))
+        .unwrap_or(0)
+}
+
+//按挂点把各部件的图片拼成一帧,身体的原点在脚下
+pub fn compose(sprites: &[&PartSprite]) -> Vec<PlacedSprite> {
+    //挂点在wz坐标系(y向下)中的位置
+    let mut anchors: HashMap<&str, Vec2> = HashMap::new();
+    let mut positions: Vec<Option<Vec2>> = vec![None; sprites.len()];
+    //身体先放在原点,其他部件依次挂在已知的挂点上
+    if let Some(body) = sprites.iter().position(|sprite| sprite.name == "body") {
+        positions[body] = Some(Vec2::ZERO);
+        for (name, point) in &sprites[body].map {
+            anchors.insert(name, Vec2::from(*point));
+        }
+    }
+    loop {
+        let mut placed = false;
+        for (i, sprite) in sprites.iter().enumerate() {
+            if positions[i].is_some() {
+                continue;
+            }
+            let Some(position) = sprite.map.iter().find_map(|(name, point)| {
+                anchors
+                    .get(name.as_str())
+                    .map(|anchor| *anchor - Vec2::from(*point))
+            }) else {
+                continue;
+            };
+            positions[i] = Some(position);
+            for (name, point) in &sprite.map {
+                anchors.entry(name).or_insert(position + Vec2::from(*point));
+            }
+            placed = true;
+        }
+        if !placed {
+            break;
+        }
+    }
+    sprites
+        .iter()
+        .zip(positions)
+        .map(|(sprite, position)| {
+            //找不到挂点的图片放在原点
+            let position = position.unwrap_or(Vec2::ZERO);
+            PlacedSprite {
+                resource_url: sprite.resource_url.clone(),
+                position: Vec2::new(position.x, -position.y),
+                anchor: Vec2::new(
+                    cal_ax(sprite.origin_x as f32, sprite.width as f32),
+                    -cal_ay(sprite.origin_y as f32, sprite.height as f32),
+                ),
+                z: z_index(&sprite.z) as f32 * PART_Z_STEP,
+            }
+        })
+        .collect()
+}
+
+//换装,id为None时卸下
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Event)]
+pub struct Equip {
+    pub part: AvatarPart,
+    pub id: Option<i32>,
+}
+
+impl Equip {
+    //穿上物品,部件由id决定
+    pub fn item(id: i32) -> Option<Equip> {
+        AvatarPart::from_id(id).map(|part| Equip { part, id: Some(id) })
+    }
+}
+
+//由部件拼成的角色,部件图片是root的子实体
+#[derive(Debug, Component, Clone)]
+pub struct Avatar {
+    pub parts: BTreeMap<AvatarPart, Handle<CharacterPart>>,
+    pub action: String,
+    pub frame: usize,
+    pub timer: Timer,
+    pub root: Entity,
+    //root下的部件图片,换帧时复用,只在数量变化时增删
+    pub sprites: Vec<Entity>,
+    //部件或帧改变后需要重新拼装
+    pub dirty: bool,
+}
+
+impl Avatar {
+    //新手的默认外观
+    pub fn default_look() -> Vec<i32> {
+        vec![
+            2000, 12000, 20000, 30000, 1040002, 1060002, 1072001, 1302000,
+        ]
+    }
+}
+
+pub struct AvatarPlugin;
+
+impl Plugin for AvatarPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_asset::<CharacterPart>()
+            .init_asset_loader::<CharacterPartLoader>()
+            .add_event::<Equip>()
+            .add_systems(
+                Update,
+                (
+                    setup_avatar,
+                    equip_avatar,
+                    update_avatar_action,
+                    animate_avatar,
+                    draw_avatar,
+                )
+                    .chain(),
+            );
+    }
+}
+
+//人物生成后,有角色部件时穿上默认外观
+fn setup_avatar(
+    mut commands: Commands,
+    query: Query<Entity, Added<Player>>,
+    mut equip_ev: EventWriter<Equip>,
+) {
+    for entity in query.iter() {
+        if !Path::new(CHARACTER_DIR).is_dir() {
+            debug!("{} not found, player uses flat sprites", CHARACTER_DIR);
+            return;
+        }
+        //root在人物脚下,左右翻转只改变root
+        let root = commands
+            .spawn(SpatialBundle::from_transform(Transform::from_xyz(
+                0.0,
+                -PLAYER_HALF_HEIGHT,
+                0.0,
+            )))
+            .id();
+        commands.entity(entity).add_child(root).insert(Avatar {
+            parts: BTreeMap::new(),
+            action: PlayerState::Fall.animation(None).to_string(),
+            frame: 0,
+            timer: Timer::from_seconds(0.0, TimerMode::Once),
+            root,
+            sprites: Vec::new(),
+            dirty: true,
+        });
+        equip_ev.send_batch(Avatar::default_look().into_iter().filter_map(Equip::item));
+    }
+}
+
+fn equip_avatar(
+    asset_server: Res<AssetServer>,
+    mut equip_ev: EventReader<Equip>,
+    mut query: Query<&mut Avatar>,
+) {
+    for equip in equip_ev.iter() {
+        for mut avatar in query.iter_mut() {
+            match equip.id {
+                Some(id) => {
+                    let handle = asset_server.load(equip.part.path(id));
+                    avatar.parts.insert(equip.part, handle);
+                }
+                None => {
+                    avatar.parts.remove(&equip.part);
+                }
+            }
+            avatar.dirty = true;
+        }
+    }
+}
+
+fn update_avatar_action(
+    mut state_ev: EventReader<PlayerStateChanged>,
+    mut query: Query<(&mut Avatar, Option<&Climb>)>,
+) {
+    let Some(changed) = state_ev.iter().last() else {
+        return;
+    };
+    for (mut avatar, climb) in query.iter_mut() {
+        let action = changed.to.animation(climb);
+        if avatar.action != action {
+            avatar.action = action.to_string();
+            avatar.frame = 0;
+            avatar.timer = Timer::from_seconds(0.0, TimerMode::Once);
+            avatar.dirty = true;
+        }
+    }
+}
+
+//按身体每一帧的delay切换帧
+fn animate_avatar(
+    time: Res<Time>,
+    parts: Res<Assets<CharacterPart>>,
+    mut query: Query<&mut Avatar>,
+) {
+    for mut avatar in query.iter_mut() {
+        if !avatar.timer.tick(time.delta()).finished() {
+            continue;
+        }
+        let Some(body) = avatar
+            .parts
+            .get(&AvatarPart::Body)
+            .and_then(|handle| parts.get(handle))
+        else {
+            continue;
+        };
+        let count = body.frames(&avatar.action).len();
+        if count == 0 {
+            continue;
+        }
+        let frame = (avatar.frame + 1) % count;
+        let delay = body.frames(&avatar.action)[frame].delay;
+        avatar.frame = frame;
+        avatar.timer = Timer::from_seconds(delay as f32 / 1000.0, TimerMode::Once);
+        avatar.dirty = true;
+    }
+}
+
+//所有部件读取完成后更新部件图片,并隐藏人物原来的整张图片
+fn draw_avatar(
+    mut commands: Commands,
+    asset_server: Res<AssetServer>,
+    parts: Res<Assets<CharacterPart>>,
+    mut query: Query<(&mut Avatar, &mut TextureAtlasSprite, &Direction)>,
+    mut q_root: Query<&mut Transform, Without<Sprite>>,
+    mut q_part: Query<(&mut Transform, &mut Sprite, &mut Handle<Image>)>,
+) {
+    for (mut avatar, mut sprite, direction) in query.iter_mut() {
+        let Ok(mut transform) = q_root.get_mut(avatar.root) else {
+            continue;
+        };
+        //图片默认朝左
+        transform.scale.x = match direction {
+            Direction::Right => -1.0,
+            Direction::Left => 1.0,
+        };
+        if !avatar.dirty {
+            continue;
+        }
+        let loading = avatar
+            .parts
+            .values()
+            .any(|handle| asset_server.get_load_state(handle) == LoadState::Loading);
+        if loading {
+            continue;
+        }
+        avatar.dirty = false;
+        let mut sprites = Vec::new();
+        for handle in avatar.parts.values() {
+            if let Some(frame) = parts
+                .get(handle)
+                .and_then(|part| part.frame(&avatar.action, avatar.frame))
+            {
+                sprites.extend(frame.sprites.iter());
+            }
+        }
+        let placed = compose(&sprites);
+        //没有可用的部件时继续显示原来的图片
+        sprite
+            .color
+            .set_a(if placed.is_empty() { 1.0 } else { 0.0 });
+
+        //多余的部件图片删除,例如卸下装备后
+        let keep = placed.len().min(avatar.sprites.len());
+        for entity in avatar.sprites.drain(keep..) {
+            commands.entity(entity).despawn();
+        }
+        for (entity, placed) in avatar.sprites.iter().zip(&placed) {
+            if let Ok((mut transform, mut sprite, mut texture)) = q_part.get_mut(*entity) {
+                transform.translation = placed.position.extend(placed.z);
+                sprite.anchor = Anchor::Custom(placed.anchor);
+                let handle = asset_server.load(&placed.resource_url);
+                if *texture != handle {
+                    *texture = handle;
+                }
+            }
+        }
+        //不够时补充新的部件图片
+        let root = avatar.root;
+        for placed in placed.iter().skip(avatar.sprites.len()) {
+            let entity = commands
+                .spawn(SpriteBundle {
+                    texture: asset_server.load(&placed.resource_url),
+                    transform: Transform::from_translation(placed.position.extend(placed.z)),
+                    sprite: Sprite {
+                        anchor: Anchor::Custom(placed.anchor),
+                        ..default()
+                    },
+                    ..default()
+                })
+                .id();
+            commands.entity(root).add_child(entity);
+            avatar.sprites.push(entity);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn part(name: &str, z: &str, map: &[(&str, [f32; 2])]) -> PartSprite {
+        PartSprite {
+            name: name.to_string(),
+            width: 20,
+            height: 30,
+            origin_x: 10,
+            origin_y: 30,
+            z: z.to_string(),
+            map: map
+                .iter()
+                .map(|(name, point)| (name.to_string(), *point))
+                .collect(),
+            resource_url: format!("{}.png", name),
+        }
+    }
+
+    #[test]
+    fn compose_places_parts_by_attachment_points() {
+        //wz坐标,y轴向下,挂点相对各自原点
+        let body = part(
+            "body",
+            "body",
+            &[("navel", [-8.0, -21.0]), ("neck", [-4.0, -32.0])],
+        );
+        let arm = part(
+            "arm",
+            "arm",
+            &[("navel", [-12.0, 2.0]), ("hand", [1.0, 5.0])],
+        );
+        let head = part(
+            "head",
+            "head",
+            &[("neck", [0.0, 15.0]), ("brow", [-4.0, -5.0])],
+        );
+        //face只能挂在head提供的brow上
+        let face = part("face", "face", &[("brow", [1.0, -10.0])]);
+        let placed = compose(&[&face, &head, &arm, &body]);
+
+        let position = |url: &str| {
+            placed
+                .iter()
+                .find(|placed| placed.resource_url == url)
+                .unwrap()
+                .position
+        };
+        assert_eq!(position("body.png"), Vec2::ZERO);
+        //neck(-4,-32) - (0,15) = (-4,-47),转换为y轴向上
+        assert_eq!(position("head.png"), Vec2::new(-4.0, 47.0));
+        //navel(-8,-21) - (-12,2) = (4,-23)
+        assert_eq!(position("arm.png"), Vec2::new(4.0, 23.0));
+        //brow(-4,-47)+(-4,-5) = (-8,-52), -(1,-10) = (-9,-42)
+        assert_eq!(position("face.png"), Vec2::new(-9.0, 42.0));
+    }
+
+    #[test]
+    fn compose_orders_parts_by_zmap_and_anchors_at_origin() {
+        let body = part("body", "body", &[]);
+        let arm = part("arm", "arm", &[]);
+        let hair = part("hair", "hairBelowBody", &[]);
+        let placed = compose(&[&arm, &body, &hair]);
+        assert!(placed[2].z < placed[1].z && placed[1].z < placed[0].z);
+        //原点在底部中间
+        assert_eq!(placed[1].anchor, Vec2::new(0.0, -0.5));
+    }
+}
@@ -1,162 +1,183 @@
-use bevy::{prelude::*, window::PrimaryWindow};
+use bevy::{
+    diagnostic::{Diagnostic, DiagnosticId, Diagnostics, RegisterDiagnostic},
+    prelude::*,
+    window::PrimaryWindow,
+};
 
-use crate::utils::{cal_ax, cal_ay};
+use crate::{
+    map::{MapEntity, SpriteResource},
+    utils::{cal_ax, cal_ay},
+};
 
 #[derive(Component, Default, Reflect, Debug)]
 #[reflect(Component)]
 pub struct BackEnity;
 
+//背景图片的对象池,镜头移动时只修改位置,数量变化时在末尾增删
+#[derive(Component, Default, Debug)]
+pub struct BackTiles {
+    pub entities: Vec<Entity>,
+    pub resource_url: String,
+    pub texture: Handle<Image>,
+}
+
 #[derive(Debug, Resource)]
 pub struct BackGroundEdge {
     pub left: f32,
     pub right: f32,
 }
 
+//前景背景的z,composite_zindex最大为655.35,镜头可见范围到999.9
+pub const FRONT_BACK_Z: f32 = 900.0;
+
+//背景图片数量,--diagnostics时输出
+pub const BACK_TILE_COUNT: DiagnosticId =
+    DiagnosticId::from_u128(204917245783146207533716201845023567311);
+
 pub struct BackGroundPlugin;
 
 impl Plugin for BackGroundPlugin {
     fn build(&self, app: &mut App) {
-        app.add_systems(Update, background);
+        app.register_diagnostic(Diagnostic::new(BACK_TILE_COUNT, "back_tiles", 20))
+            .add_systems(
+                Update,
+                (
+                    animate_background,
+                    scroll_background,
+                    background,
+                    back_tile_diagnostic,
+                )
+                    .chain(),
+            );
     }
 }
 
+fn back_tile_diagnostic(mut diagnostics: Diagnostics, q_tile: Query<(), With<BackEnity>>) {
+    diagnostics.add_measurement(BACK_TILE_COUNT, || q_tile.iter().count() as f64);
+}
+
 //绘制背景,且背景随人物移动
 fn background(
-    time: Res<Time>,
     mut commands: Commands,
-    mut q_backgroud: Query<&mut BackGround>,
-    mut q_transform: Query<(&mut Transform, &mut OrthographicProjection), With<Camera>>,
+    mut q_backgroud: Query<(Entity, Ref<BackGround>, Option<&mut BackTiles>)>,
+    q_transform: Query<&Transform, (With<Camera>, Without<BackEnity>)>,
     mut q_window: Query<&Window, With<PrimaryWindow>>,
-    mut q_backenity: Query<Entity, With<BackEnity>>,
+    mut q_tile: Query<(&mut Transform, &mut Sprite, &mut Handle<Image>), With<BackEnity>>,
     asset_server: Res<AssetServer>,
-    mut last: Local<Vec3>,
+    //上次绘制时的镜头位置和窗口大小
+    mut last: Local<(Vec3, Vec2)>,
 ) {
-    let transform = q_transform.get_single_mut().ok().unwrap().0;
+    let transform = q_transform.get_single().ok().unwrap();
     let window = q_window.get_single_mut().ok().unwrap();
 
-    if last.eq(&transform.translation) {
-        return;
-    } else {
-        *last = transform.translation;
-    }
-    
-    for backenity in q_backenity.iter_mut() {
-        commands.entity(backenity).despawn();
-    }
-
-    for backgroud in q_backgroud.iter_mut() {
-        let res: serde_json::Value = serde_json::from_str(&backgroud.resource).unwrap();
-        let cx;
-        let cy;
-        if backgroud.cx == 0 {
-            cx = res["Width"].as_i64().unwrap() as i32;
-        } else {
-            cx = backgroud.cx;
-        }
+    //窗口大小变化时可见范围也会变化,移动鼠标也会修改Window,所以只比较宽高
+    let size = Vec2::new(window.width(), window.height());
+    let camera_moved = !last.eq(&(transform.translation, size));
+    *last = (transform.translation, size);
 
-        if backgroud.cy == 0 {
-            cy = res["Height"].as_i64().unwrap() as i32;
-        } else {
-            cy = backgroud.cy;
+    for (entity, backgroud, tiles) in q_backgroud.iter_mut() {
+        //镜头没有移动时只重新绘制新生成、换帧或自动滚动的背景
+        if !camera_moved && !backgroud.is_changed() && tiles.is_some() {
+            continue;
         }
-        let mut position_offset_x = 0;
-        let mut position_offset_y = 0;
+        let positions = backgroud.tile_positions(transform.translation, size);
+        let anchor = backgroud.anchor();
+        let res = &backgroud.resource;
 
-        if backgroud.tilemode.auto_scroll_x == true {
-            position_offset_x += (backgroud.rx as f32 * 5.0 * time.delta_seconds()) as i32;
-            position_offset_x %= cx;
-        } else {
-            position_offset_x =
-                (transform.translation.x as f32 * (backgroud.rx + 100) as f32 / 100.0) as i32;
+        //第一次绘制时生成对象池
+        let mut new_tiles = BackTiles::default();
+        let tiles = match tiles {
+            Some(tiles) => tiles.into_inner(),
+            None => &mut new_tiles,
+        };
+        //换帧后才重新读取图片
+        if tiles.resource_url != res.resource_url {
+            tiles.resource_url = res.resource_url.clone();
+            tiles.texture = asset_server.load(&res.resource_url);
         }
-
-        if backgroud.tilemode.auto_scroll_y == true {
-            position_offset_y += (backgroud.ry as f32 * 5.0 * time.delta_seconds()) as i32;
-            position_offset_y %= cy;
-        } else {
-            position_offset_y =
-                (transform.translation.y as f32 * (backgroud.ry + 100) as f32 / 100.0) as i32;
+        //多余的图片在边缘移出屏幕后删除
+        for tile in tiles
+            .entities
+            .drain(positions.len().min(tiles.entities.len())..)
+        {
+            commands.entity(tile).despawn();
         }
-
-        let mut base_pos_x = backgroud.x + position_offset_x;
-        let mut base_pos_y = backgroud.y + position_offset_y;
-
-        let mut x = base_pos_x as f32;
-        let mut y = base_pos_y as f32;
-        let mut z: f32;
-
-        let mut tile_count_x = 1;
-        let mut tile_count_y = 1;
-        let screen_left = transform.translation.x as i32 - window.width() as i32 / 2;
-        // let screen_left = 0;
-        let screen_right = screen_left + window.width() as i32;
-
-        let screen_top = transform.translation.y as i32 + window.height() as i32 / 2;
-        let screen_bottom = screen_top - window.height() as i32;
-
-        if backgroud.tilemode.tile_x && cx > 0 {
-            if x <= screen_left as f32 {
-                while x <= screen_left as f32 {
-                    x += cx as f32;
-                }
-                x -= cx as f32;
-            } else {
-                while x > screen_left as f32 {
-                    x -= cx as f32;
+        for (tile, position) in tiles.entities.iter().zip(&positions) {
+            if let Ok((mut transform, mut sprite, mut texture)) = q_tile.get_mut(*tile) {
+                transform.translation = *position;
+                sprite.anchor = bevy::sprite::Anchor::Custom(anchor);
+                if *texture != tiles.texture {
+                    *texture = tiles.texture.clone();
                 }
             }
-            tile_count_x += (screen_right - x as i32) / cx + 1;
         }
-
-        if backgroud.tilemode.tile_y && cy > 0 {
-            if y <= screen_bottom as f32 {
-                while y <= screen_bottom as f32 {
-                    y += cy as f32;
-                }
-                y -= cy as f32;
-            } else {
-                while y > screen_bottom as f32 {
-                    y -= cy as f32;
-                }
-            }
-            tile_count_y += (screen_top - y as i32) / cy + 1;
+        //不够时在边缘补充新的图片
+        for position in positions.iter().skip(tiles.entities.len()) {
+            let tile = commands
+                .spawn(tile_bundle(&backgroud, tiles.texture.clone(), *position))
+                .id();
+            tiles.entities.push(tile);
         }
-
-        // println!("tile_count_y:{:?}", tile_count_y);
-
-        if backgroud.front == true {
-            z = -10.0 + backgroud.id as f32 / 10.0;
-        } else {
-            z = -20.0 + backgroud.id as f32 / 10.0;
+        if !new_tiles.entities.is_empty() {
+            commands.entity(entity).insert(new_tiles);
         }
+    }
+}
 
-        let ox = cal_ax(
-            res["OriginX"].as_f64().unwrap() as f32,
-            res["Width"].as_f64().unwrap() as f32,
-        );
+//背景的一张图片
+pub fn tile_bundle(
+    backgroud: &BackGround,
+    texture: Handle<Image>,
+    position: Vec3,
+) -> (SpriteBundle, BackEnity, MapEntity) {
+    let color = Color::rgba(1.0, 1.0, 1.0, backgroud.alpha as f32 / 255.0);
+    (
+        SpriteBundle {
+            texture,
+            transform: Transform::from_translation(position),
+            sprite: Sprite {
+                anchor: bevy::sprite::Anchor::Custom(backgroud.anchor()),
+                flip_x: backgroud.flip_x,
+                color,
+                ..default()
+            },
+            ..default()
+        },
+        BackEnity,
+        MapEntity,
+    )
+}
 
-        let oy = -cal_ay(
-            res["OriginY"].as_f64().unwrap() as f32,
-            res["Height"].as_f64().unwrap() as f32,
-        );
+//自动滚动的背景(Type 4-7)每帧累加偏移,按cx/cy取余后无缝循环,镜头不动时也继续滚动
+fn scroll_background(time: Res<Time>, mut q_backgroud: Query<&mut BackGround>) {
+    for mut backgroud in q_backgroud.iter_mut() {
+        let (cx, cy) = backgroud.tile_size();
+        if backgroud.tilemode.auto_scroll_x && cx > 0 {
+            let offset = backgroud.rx as f32 * 5.0 * time.delta_seconds();
+            backgroud.scroll.x = (backgroud.scroll.x + offset).rem_euclid(cx as f32);
+        }
+        //wz的y轴向下
+        if backgroud.tilemode.auto_scroll_y && cy > 0 {
+            let offset = backgroud.ry as f32 * 5.0 * time.delta_seconds();
+            backgroud.scroll.y = (backgroud.scroll.y - offset).rem_euclid(cy as f32);
+        }
+    }
+}
 
-        for j in 0..tile_count_y {
-            for i in 0..tile_count_x {
-                commands.spawn((
-                    SpriteBundle {
-                        texture: asset_server
-                            .load(res["ResourceUrl"].to_string().replace("\"", "")),
-                        transform: Transform::from_xyz(x + (i * cx) as f32, y + (j * cy) as f32, z),
-                        sprite: Sprite {
-                            anchor: bevy::sprite::Anchor::Custom(Vec2::new(ox, oy)),
-                            ..default()
-                        },
-                        ..default()
-                    },
-                    BackEnity,
-                ));
-            }
+//动画背景(Ani=1)按每帧的delay切换图片
+fn animate_background(time: Res<Time>, mut q_backgroud: Query<&mut BackGround>) {
+    for mut backgroud in q_backgroud.iter_mut() {
+        if backgroud.frames.len() <= 1 {
+            continue;
         }
+        if !backgroud.timer.tick(time.delta()).finished() {
+            continue;
+        }
+        let frame = (backgroud.frame + 1) % backgroud.frames.len();
+        let delay = backgroud.delays[frame];
+        backgroud.frame = frame;
+        backgroud.resource = backgroud.frames[frame].clone();
+        backgroud.timer = Timer::from_seconds(delay, TimerMode::Once);
     }
 }
 
@@ -174,8 +195,16 @@ pub struct BackGround {
     pub front: bool,
     pub ani: i32,
     pub types: i32,
-    pub resource: String,
+    pub resource: SpriteResource,
     pub tilemode: Tilemode,
+    //动画背景的所有帧,resource为当前帧
+    pub frames: Vec<SpriteResource>,
+    //每帧时间,秒
+    pub delays: Vec<f32>,
+    pub frame: usize,
+    pub timer: Timer,
+    //自动滚动累计的偏移,像素
+    pub scroll: Vec2,
 }
 
 pub struct Tilemode {
@@ -199,7 +228,7 @@ impl BackGround {
         front: bool,
         ani: i32,
         types: i32,
-        resource: String,
+        resource: SpriteResource,
     ) -> BackGround {
         let tilemode;
         match types {
@@ -289,8 +318,308 @@ impl BackGround {
             front,
             ani,
             types,
+            frames: vec![resource.clone()],
+            delays: vec![0.0],
             resource,
             tilemode,
+            frame: 0,
+            timer: Timer::default(),
+            scroll: Vec2::ZERO,
+        }
+    }
+
+    //平铺的间隔,cx/cy为0时使用图片大小
+    pub fn tile_size(&self) -> (i32, i32) {
+        let cx = if self.cx == 0 {
+            self.resource.width
+        } else {
+            self.cx
+        };
+        let cy = if self.cy == 0 {
+            self.resource.height
+        } else {
+            self.cy
+        };
+        (cx, cy)
+    }
+
+    //镜头在camera、窗口大小为size时需要绘制的所有图片位置
+    pub fn tile_positions(&self, camera: Vec3, size: Vec2) -> Vec<Vec3> {
+        let (cx, cy) = self.tile_size();
+        let position_offset_x;
+        let position_offset_y;
+
+        //自动滚动的背景使用累计的偏移,否则随镜头视差移动
+        if self.tilemode.auto_scroll_x {
+            position_offset_x = self.scroll.x as i32;
+        } else {
+            position_offset_x = (camera.x * (self.rx + 100) as f32 / 100.0) as i32;
+        }
+
+        if self.tilemode.auto_scroll_y {
+            position_offset_y = self.scroll.y as i32;
+        } else {
+            position_offset_y = (camera.y * (self.ry + 100) as f32 / 100.0) as i32;
+        }
+
+        let mut x = (self.x + position_offset_x) as f32;
+        let mut y = (self.y + position_offset_y) as f32;
+
+        let mut tile_count_x = 1;
+        let mut tile_count_y = 1;
+        let screen_left = camera.x as i32 - size.x as i32 / 2;
+        let screen_right = screen_left + size.x as i32;
+
+        let screen_top = camera.y as i32 + size.y as i32 / 2;
+        let screen_bottom = screen_top - size.y as i32;
+
+        if self.tilemode.tile_x && cx > 0 {
+            if x <= screen_left as f32 {
+                while x <= screen_left as f32 {
+                    x += cx as f32;
+                }
+                x -= cx as f32;
+            } else {
+                while x > screen_left as f32 {
+                    x -= cx as f32;
+                }
+            }
+            tile_count_x += (screen_right - x as i32) / cx + 1;
+        }
+
+        if self.tilemode.tile_y && cy > 0 {
+            if y <= screen_bottom as f32 {
+                while y <= screen_bottom as f32 {
+                    y += cy as f32;
+                }
+                y -= cy as f32;
+            } else {
+                while y > screen_bottom as f32 {
+                    y -= cy as f32;
+                }
+            }
+            tile_count_y += (screen_top - y as i32) / cy + 1;
+        }
+
+        //前景画在所有layer、传送门和人物之上,按ID从小到大依次覆盖
+        let z = if self.front {
+            FRONT_BACK_Z + self.id as f32 / 100.0
+        } else {
+            -20.0 + self.id as f32 / 10.0
+        };
+
+        let mut positions = Vec::new();
+        for j in 0..tile_count_y {
+            for i in 0..tile_count_x {
+                positions.push(Vec3::new(x + (i * cx) as f32, y + (j * cy) as f32, z));
+            }
+        }
+        positions
+    }
+
+    //图片原点对应的锚点
+    pub fn anchor(&self) -> Vec2 {
+        let mut ox = cal_ax(self.resource.origin_x as f32, self.resource.width as f32);
+        //翻转后原点也要翻转
+        if self.flip_x {
+            ox = -ox;
+        }
+
+        let oy = -cal_ay(self.resource.origin_y as f32, self.resource.height as f32);
+        Vec2::new(ox, oy)
+    }
+
+    pub fn with_frames(mut self, frames: Vec<SpriteResource>, delays: Vec<f32>) -> BackGround {
+        self.timer = Timer::from_seconds(delays[0], TimerMode::Once);
+        self.frames = frames;
+        self.delays = delays;
+        self
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    //生成和删除的背景图片数量
+    #[derive(Resource, Default)]
+    struct Churn {
+        spawned: usize,
+        despawned: usize,
+        //最近一帧重新设置位置的图片数量
+        redrawn: usize,
+    }
+
+    fn count_churn(
+        q_added: Query<(), Added<BackEnity>>,
+        q_redrawn: Query<(), (With<BackEnity>, Changed<Transform>)>,
+        mut removed: RemovedComponents<BackEnity>,
+        mut churn: ResMut<Churn>,
+    ) {
+        churn.spawned += q_added.iter().count();
+        churn.despawned += removed.iter().count();
+        churn.redrawn = q_redrawn.iter().count();
+    }
+
+    fn resource(width: i32, height: i32) -> SpriteResource {
+        SpriteResource {
+            width,
+            height,
+            origin_x: 0,
+            origin_y: 0,
+            z: 0,
+            resource_url: "back.png".to_string(),
+        }
+    }
+
+    //优化前的做法:镜头移动后删除全部图片再重新生成
+    fn respawn_background(
+        mut commands: Commands,
+        q_backgroud: Query<&BackGround>,
+        q_camera: Query<&Transform, With<Camera>>,
+        q_window: Query<&Window, With<PrimaryWindow>>,
+        q_tile: Query<Entity, With<BackEnity>>,
+        asset_server: Res<AssetServer>,
+        mut last: Local<Option<Vec3>>,
+    ) {
+        let camera = q_camera.single().translation;
+        let window = q_window.single();
+        if *last == Some(camera) {
+            return;
+        }
+        *last = Some(camera);
+        for tile in q_tile.iter() {
+            commands.entity(tile).despawn();
+        }
+        let size = Vec2::new(window.width(), window.height());
+        for backgroud in q_backgroud.iter() {
+            for position in backgroud.tile_positions(camera, size) {
+                let texture = asset_server.load(&backgroud.resource.resource_url);
+                commands.spawn(tile_bundle(backgroud, texture, position));
+            }
+        }
+    }
+
+    //800x600窗口,100x100的背景,draw为绘制背景的system
+    fn app<M>(backs: Vec<BackGround>, draw: impl IntoSystemConfigs<M>) -> App {
+        let mut app = App::new();
+        app.add_plugins((MinimalPlugins, AssetPlugin::default()))
+            .init_resource::<Churn>()
+            .add_systems(
+                Update,
+                (animate_background, scroll_background, draw).chain(),
+            )
+            .add_systems(PostUpdate, count_churn);
+        app.world.spawn((
+            Window {
+                resolution: (800.0, 600.0).into(),
+                ..default()
+            },
+            PrimaryWindow,
+        ));
+        app.world.spawn((Camera::default(), Transform::default()));
+        for back in backs {
+            app.world.spawn(back);
+        }
+        app
+    }
+
+    fn back(id: i32, rx: i32, types: i32) -> BackGround {
+        BackGround::new(
+            id,
+            0,
+            0,
+            0,
+            0,
+            rx,
+            0,
+            255,
+            false,
+            false,
+            0,
+            types,
+            resource(100, 100),
+        )
+    }
+
+    fn pool_size(app: &mut App, id: i32) -> usize {
+        let mut q_back = app.world.query::<(&BackGround, &BackTiles)>();
+        q_back
+            .iter(&app.world)
+            .find(|(back, _)| back.id == id)
+            .map_or(0, |(_, tiles)| tiles.entities.len())
+    }
+
+    fn move_camera(app: &mut App, offset: Vec3) {
+        let mut q_camera = app.world.query_filtered::<&mut Transform, With<Camera>>();
+        q_camera.single_mut(&mut app.world).translation += offset;
+    }
+
+    fn window(app: &mut App) -> Mut<'_, Window> {
+        let mut q_window = app.world.query::<&mut Window>();
+        q_window.single_mut(&mut app.world)
+    }
+
+    fn tile_count(app: &mut App) -> usize {
+        let mut q_tile = app.world.query_filtered::<(), With<BackEnity>>();
+        q_tile.iter(&app.world).count()
+    }
+
+    const MOVES: usize = 300;
+
+    //镜头移动MOVES次,返回第一帧之后生成和删除的图片数量
+    fn churn_over_moves<M>(draw: impl IntoSystemConfigs<M>) -> (usize, usize) {
+        //一个横竖平铺,一个横向平铺且视差移动
+        let mut app = app(vec![back(0, 0, 3), back(1, -50, 1)], draw);
+        app.update();
+        let first = tile_count(&mut app);
+        assert_eq!(app.world.resource::<Churn>().spawned, first);
+        for _ in 0..MOVES {
+            move_camera(&mut app, Vec3::new(37.0, -13.0, 0.0));
+            app.update();
+            assert_eq!(tile_count(&mut app), first);
         }
+        let churn = app.world.resource::<Churn>();
+        (churn.spawned - first, churn.despawned)
+    }
+
+    //同样移动镜头,对象池不再生成和删除图片,之前每次移动都删除全部90张再重新生成
+    #[test]
+    fn camera_moves_reuse_back_tiles() {
+        assert_eq!(churn_over_moves(background), (0, 0));
+        assert_eq!(
+            churn_over_moves(respawn_background),
+            (90 * MOVES, 90 * MOVES)
+        );
+    }
+
+    //镜头不动时只重新绘制自动滚动的背景,静止的背景不再每帧重新设置位置
+    #[test]
+    fn still_camera_only_redraws_changed_backs() {
+        let mut app = app(vec![back(0, 0, 3), back(1, 10, 4)], background);
+        app.update();
+        let all = tile_count(&mut app);
+        let scrolling = pool_size(&mut app, 1);
+        assert!(scrolling > 0 && scrolling < all);
+
+        for _ in 0..10 {
+            app.update();
+            assert_eq!(app.world.resource::<Churn>().redrawn, scrolling);
+        }
+
+        //移动鼠标会修改Window,但不需要重新绘制
+        window(&mut app).set_cursor_position(Some(Vec2::new(10.0, 20.0)));
+        app.update();
+        assert_eq!(app.world.resource::<Churn>().redrawn, scrolling);
+
+        move_camera(&mut app, Vec3::new(37.0, -13.0, 0.0));
+        app.update();
+        assert_eq!(app.world.resource::<Churn>().redrawn, all);
+
+        window(&mut app).resolution.set(1024.0, 768.0);
+        app.update();
+        let resized = tile_count(&mut app);
+        assert!(resized > all);
+        assert_eq!(app.world.resource::<Churn>().redrawn, resized);
     }
 }
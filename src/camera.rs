@@ -15,7 +15,10 @@ pub struct CameraPlugin;
 
 impl Plugin for CameraPlugin {
     fn build(&self, app: &mut App) {
-        app.add_systems(Update,camera_follow);
+        app.add_systems(
+            Update,
+            camera_follow.run_if(resource_exists::<BackGroundEdge>()), //地图加载完成后才有边界
+        );
     }
 }
 
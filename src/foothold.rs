@@ -1,9 +1,19 @@
-use std::default;
+use std::{
+    cmp::{max, min},
+    default,
+};
 
-use bevy::{prelude::*, render::render_phase::PhaseItem, window::PrimaryWindow};
+use bevy::{
+    prelude::*,
+    render::render_phase::PhaseItem,
+    utils::{HashMap, HashSet},
+    window::PrimaryWindow,
+};
 use bevy_rapier2d::geometry::Group;
+use serde::Deserialize;
 
-#[derive(Component, Debug)]
+#[derive(Component, Debug, Clone, Deserialize)]
+#[serde(rename_all = "PascalCase")]
 pub struct FootHold {
     pub x1: i32,
     pub y1: i32,
@@ -13,9 +23,13 @@ pub struct FootHold {
     pub next: i32,
     pub piece: i32,
     pub layer: i32,
+    #[serde(rename = "ID")]
     pub id: i32,
 }
 
+//所有非垂直地砖都属于FLOOR_GROUP,人物在空中时能与所有layer的地板发生碰撞
+pub const FLOOR_GROUP: Group = Group::GROUP_32;
+
 #[derive(Debug, Component, Clone, PartialEq, Default)]
 pub enum FootHoldType {
     #[default]
@@ -40,6 +54,16 @@ pub enum FootHoldType {
 */
 
 impl FootHold {
+    //垂直的地砖是墙
+    pub fn is_wall(&self) -> bool {
+        self.x1 == self.x2
+    }
+
+    //从右往左画的水平地砖只有从下往上会碰撞,不能站在上面
+    pub fn is_ceiling(&self) -> bool {
+        self.y1 == self.y2 && self.x1 > self.x2
+    }
+
     /// Creates a new collision-groups with the given membership masks and filter masks.
     pub fn get_foothold_group(p1: Vec2, p2: Vec2) -> Group {
         //先判断平行
@@ -103,8 +127,350 @@ impl FootHold {
             }
         }
     }
-    //当人物在空中,获取人物落点的地砖layer
-    pub fn get_bottom_layer(p: Vec2) -> i32 {
-        0
+    //当人物在空中,获取人物落点的地砖layer和ID
+    pub fn get_bottom_layer(graph: &FootholdGraph, p: Vec2) -> Option<(i32, i32)> {
+        graph
+            .find_below(p)
+            .map(|foothold| (foothold.layer, foothold.id))
+    }
+
+    //地砖在x处的高度(bevy坐标),x不在地砖范围内或地砖是墙时返回None
+    pub fn y_at(&self, x: f32) -> Option<f32> {
+        let (x1, y1, x2, y2) = (
+            self.x1 as f32,
+            self.y1 as f32,
+            self.x2 as f32,
+            self.y2 as f32,
+        );
+        if self.is_wall() || x < x1.min(x2) || x > x1.max(x2) {
+            return None;
+        }
+        Some(-(y1 + (y2 - y1) * (x - x1) / (x2 - x1)))
+    }
+}
+
+//地砖索引,按ID查找地砖,通过Prev/Next把地砖连成链,按Piece分组
+#[derive(Debug, Resource, Clone, Default)]
+pub struct FootholdGraph {
+    pub footholds: HashMap<i32, FootHold>,
+    pub pieces: HashMap<i32, Vec<i32>>,
+    //无效的墙,创建时计算一次
+    pub invalid_walls: HashSet<i32>,
+    //所有地砖的左右边界
+    pub left: i32,
+    pub right: i32,
+}
+
+impl FootholdGraph {
+    pub fn new(footholds: &[FootHold]) -> FootholdGraph {
+        let mut graph = FootholdGraph::default();
+        for foothold in footholds {
+            graph
+                .pieces
+                .entry(foothold.piece)
+                .or_default()
+                .push(foothold.id);
+            graph.footholds.insert(foothold.id, foothold.clone());
+        }
+        graph.left = footholds
+            .iter()
+            .map(|foothold| min(foothold.x1, foothold.x2))
+            .min()
+            .unwrap_or(0);
+        graph.right = footholds
+            .iter()
+            .map(|foothold| max(foothold.x1, foothold.x2))
+            .max()
+            .unwrap_or(0);
+        graph.invalid_walls = footholds
+            .iter()
+            .filter(|foothold| graph.leads_nowhere(foothold.id))
+            .map(|foothold| foothold.id)
+            .collect();
+        graph
+    }
+
+    //Prev/Next为0时表示没有相连的地砖
+    pub fn get(&self, id: i32) -> Option<&FootHold> {
+        self.footholds.get(&id)
+    }
+
+    pub fn prev(&self, id: i32) -> Option<&FootHold> {
+        self.get(self.get(id)?.prev)
+    }
+
+    pub fn next(&self, id: i32) -> Option<&FootHold> {
+        self.get(self.get(id)?.next)
+    }
+
+    //向右走出当前地砖时接着的地砖,墙没有左右之分
+    pub fn right_of(&self, id: i32) -> Option<&FootHold> {
+        let foothold = self.get(id)?;
+        if foothold.is_wall() {
+            return None;
+        }
+        if foothold.x1 > foothold.x2 {
+            self.neighbor_at(foothold, foothold.x1, foothold.y1)
+        } else {
+            self.neighbor_at(foothold, foothold.x2, foothold.y2)
+        }
+    }
+
+    //向左走出当前地砖时接着的地砖
+    pub fn left_of(&self, id: i32) -> Option<&FootHold> {
+        let foothold = self.get(id)?;
+        if foothold.is_wall() {
+            return None;
+        }
+        if foothold.x1 < foothold.x2 {
+            self.neighbor_at(foothold, foothold.x1, foothold.y1)
+        } else {
+            self.neighbor_at(foothold, foothold.x2, foothold.y2)
+        }
+    }
+
+    //Prev/Next中端点为(x, y)的地砖
+    fn neighbor_at(&self, foothold: &FootHold, x: i32, y: i32) -> Option<&FootHold> {
+        [self.prev(foothold.id), self.next(foothold.id)]
+            .into_iter()
+            .flatten()
+            .find(|other| (other.x1 == x && other.y1 == y) || (other.x2 == x && other.y2 == y))
+    }
+
+    //p点正下方的第一块能站立的地砖,不包括墙和天花板
+    pub fn find_below(&self, p: Vec2) -> Option<&FootHold> {
+        self.footholds
+            .values()
+            .filter(|foothold| !foothold.is_ceiling())
+            .filter_map(|foothold| Some((foothold, foothold.y_at(p.x)?)))
+            .filter(|(_, y)| *y <= p.y)
+            .max_by(|a, b| a.1.total_cmp(&b.1))
+            .map(|(foothold, _)| foothold)
+    }
+
+    //垂直地砖往下延伸的地砖都是垂直的,且没有拐弯就中断时,这条地砖是无效的墙,永远不会发生碰撞
+    pub fn is_invalid_wall(&self, id: i32) -> bool {
+        self.invalid_walls.contains(&id)
+    }
+
+    //沿着墙往下找,没有拐弯就中断时返回true
+    fn leads_nowhere(&self, id: i32) -> bool {
+        let Some(mut current) = self.get(id) else {
+            return false;
+        };
+        for _ in 0..self.footholds.len() {
+            if !current.is_wall() {
+                //出现拐弯
+                return false;
+            }
+            //沿着下端点继续往下找,json中y轴向下
+            let (x, y) = if current.y1 > current.y2 {
+                (current.x1, current.y1)
+            } else {
+                (current.x2, current.y2)
+            };
+            match self.neighbor_at(current, x, y) {
+                Some(next) => current = next,
+                None => return true,
+            }
+        }
+        false
+    }
+}
+
+//沿Prev/Next和Piece查询地砖,给怪物AI和平台边缘检测使用,游戏中暂时只有测试调用
+#[allow(dead_code)]
+impl FootholdGraph {
+    //同一Piece的地砖
+    pub fn piece(&self, piece: i32) -> &[i32] {
+        self.pieces.get(&piece).map_or(&[], |ids| ids.as_slice())
+    }
+
+    //从链的第一块地砖到最后一块地砖,Prev/Next首尾相连时在回到起点前停止
+    pub fn chain(&self, id: i32) -> Vec<i32> {
+        let Some(mut first) = self.get(id) else {
+            return Vec::new();
+        };
+        for _ in 0..self.footholds.len() {
+            match self.get(first.prev) {
+                Some(prev) if prev.id != id => first = prev,
+                _ => break,
+            }
+        }
+        let mut chain = vec![first.id];
+        let mut current = first;
+        while let Some(next) = self.get(current.next) {
+            if chain.contains(&next.id) {
+                break;
+            }
+            chain.push(next.id);
+            current = next;
+        }
+        chain
+    }
+
+    //链两端的地砖ID
+    pub fn chain_endpoints(&self, id: i32) -> Option<(i32, i32)> {
+        let chain = self.chain(id);
+        Some((*chain.first()?, *chain.last()?))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::map::MapData;
+
+    fn graph() -> FootholdGraph {
+        let bytes = std::fs::read("assets/Map/Map/Map0/000010000.json").unwrap();
+        let map = MapData::from_slice(&bytes).unwrap();
+        FootholdGraph::new(&map.foot_hold)
+    }
+
+    #[test]
+    fn wall_turning_into_floor_is_valid() {
+        let graph = graph();
+        //71 -> 84 -> 92,92是地板
+        assert!(!graph.get(92).unwrap().is_wall());
+        assert!(!graph.is_invalid_wall(71));
+        assert!(!graph.is_invalid_wall(84));
+        assert!(!graph.is_invalid_wall(90));
+        assert!(!graph.is_invalid_wall(91));
+    }
+
+    #[test]
+    fn vertical_chain_without_turn_is_invalid() {
+        let graph = graph();
+        //地图左边缘 23 -> 25 -> 21 -> 20 -> 22 -> 16,16下面没有地砖
+        for id in [23, 25, 21, 20, 22, 16] {
+            assert!(graph.get(id).unwrap().is_wall());
+            assert!(graph.is_invalid_wall(id), "wall {} should be invalid", id);
+        }
+        //地图右边缘
+        for id in [103, 104, 99, 101, 72, 73, 75, 74, 76] {
+            assert!(graph.is_invalid_wall(id), "wall {} should be invalid", id);
+        }
+    }
+
+    #[test]
+    fn single_walls_without_neighbor_are_invalid() {
+        let graph = graph();
+        assert!(graph.is_invalid_wall(56));
+        assert!(graph.is_invalid_wall(61));
+    }
+
+    #[test]
+    fn floors_and_unknown_ids_are_not_invalid_walls() {
+        let graph = graph();
+        assert!(!graph.is_invalid_wall(92));
+        assert!(!graph.is_invalid_wall(-1));
+    }
+
+    //坐标和json一样y轴向下
+    fn foothold(
+        id: i32,
+        (x1, y1): (i32, i32),
+        (x2, y2): (i32, i32),
+        (prev, next): (i32, i32),
+        piece: i32,
+    ) -> FootHold {
+        FootHold {
+            x1,
+            y1,
+            x2,
+            y2,
+            prev,
+            next,
+            piece,
+            layer: 0,
+            id,
+        }
+    }
+
+    //1 -> 2 -> 3 -> 4从左往右画,4是往下的墙;6 -> 5从右往左画;7 -> 8 -> 9首尾相连
+    fn hand_built() -> FootholdGraph {
+        FootholdGraph::new(&[
+            foothold(1, (0, 0), (100, 0), (0, 2), 1),
+            foothold(2, (100, 0), (200, -50), (1, 3), 1),
+            foothold(3, (200, -50), (300, -50), (2, 4), 1),
+            foothold(4, (300, -50), (300, 50), (3, 0), 1),
+            foothold(5, (600, 10), (500, 0), (0, 6), 2),
+            foothold(6, (500, 0), (400, 0), (5, 0), 2),
+            foothold(7, (0, 200), (100, 200), (9, 8), 3),
+            foothold(8, (100, 200), (50, 250), (7, 9), 3),
+            foothold(9, (50, 250), (0, 200), (8, 7), 3),
+        ])
+    }
+
+    fn id(foothold: Option<&FootHold>) -> Option<i32> {
+        foothold.map(|foothold| foothold.id)
+    }
+
+    #[test]
+    fn walking_follows_prev_next_links() {
+        let graph = hand_built();
+        assert_eq!(id(graph.next(1)), Some(2));
+        assert_eq!(id(graph.prev(1)), None);
+        assert_eq!(id(graph.right_of(1)), Some(2));
+        assert_eq!(id(graph.right_of(2)), Some(3));
+        assert_eq!(id(graph.right_of(3)), Some(4));
+        assert_eq!(id(graph.left_of(3)), Some(2));
+        assert_eq!(id(graph.left_of(2)), Some(1));
+        assert_eq!(id(graph.left_of(1)), None);
+        //墙没有左右
+        assert_eq!(id(graph.right_of(4)), None);
+        assert_eq!(id(graph.left_of(4)), None);
+        assert_eq!(id(graph.right_of(100)), None);
+    }
+
+    #[test]
+    fn walking_follows_chains_drawn_right_to_left() {
+        let graph = hand_built();
+        //6在5的左边,Next指向更左边的地砖
+        assert_eq!(id(graph.right_of(6)), Some(5));
+        assert_eq!(id(graph.left_of(5)), Some(6));
+        assert_eq!(id(graph.right_of(5)), None);
+        assert_eq!(id(graph.left_of(6)), None);
+    }
+
+    #[test]
+    fn chain_endpoints_from_any_foothold() {
+        let graph = hand_built();
+        for start in 1..=4 {
+            assert_eq!(graph.chain(start), vec![1, 2, 3, 4]);
+            assert_eq!(graph.chain_endpoints(start), Some((1, 4)));
+        }
+        assert_eq!(graph.chain(6), vec![5, 6]);
+        assert_eq!(graph.chain_endpoints(5), Some((5, 6)));
+        assert_eq!(graph.chain(100), Vec::<i32>::new());
+        assert_eq!(graph.chain_endpoints(100), None);
+    }
+
+    #[test]
+    fn cyclic_chain_stops_before_repeating() {
+        let graph = hand_built();
+        //首尾相连时从起点的下一块开始,到起点结束
+        assert_eq!(graph.chain(8), vec![9, 7, 8]);
+        assert_eq!(graph.chain_endpoints(8), Some((9, 8)));
+        assert_eq!(graph.chain(7), vec![8, 9, 7]);
+        assert_eq!(id(graph.right_of(7)), Some(8));
+        assert_eq!(id(graph.left_of(7)), Some(9));
+    }
+
+    #[test]
+    fn invalid_walls_are_indexed_on_creation() {
+        //4往下没有拐弯就中断
+        let graph = hand_built();
+        assert_eq!(graph.invalid_walls, HashSet::from_iter([4]));
+        assert!(graph.is_invalid_wall(4));
+        assert!(!graph.is_invalid_wall(3));
+    }
+
+    #[test]
+    fn pieces_group_footholds() {
+        let graph = hand_built();
+        assert_eq!(graph.piece(1), &[1, 2, 3, 4]);
+        assert_eq!(graph.piece(3), &[7, 8, 9]);
+        assert!(graph.piece(42).is_empty());
     }
 }
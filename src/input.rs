@@ -0,0 +1,253 @@
+use std::{collections::BTreeMap, fs, io, path::Path};
+
+use bevy::{
+    input::{
+        gamepad::{GamepadConnection, GamepadConnectionEvent},
+        InputSystem,
+    },
+    prelude::*,
+};
+use serde::{Deserialize, Serialize};
+
+use crate::utils::arg_or_env;
+
+//默认的按键配置文件,相对于运行目录
+const KEYMAP_FILE: &str = "keymap.json";
+//未通过命令行 --keymap 指定时读取的环境变量
+const KEYMAP_ENV: &str = "STUDYMS_KEYMAP";
+//摇杆偏移超过这个值才算按下方向
+const STICK_DEADZONE: f32 = 0.5;
+
+//人物动作,其他系统通过Res<Input<Action>>读取,不直接读取按键和手柄
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum Action {
+    MoveLeft,
+    MoveRight,
+    Jump,
+    Crouch,
+    Up,
+    Attack,
+    Pickup,
+    //技能栏,从0开始
+    Skill(u8),
+}
+
+//按键到动作的映射,一个按键只对应一个动作,一个动作可以有多个按键
+//运行时修改keys会自动保存到文件
+#[derive(Debug, Resource, Clone, PartialEq, Serialize, Deserialize)]
+pub struct KeyMap {
+    //按键码排序,保存的文件顺序固定
+    pub keys: BTreeMap<KeyCode, Action>,
+    //手柄按键,左摇杆和十字键一样控制方向
+    #[serde(default = "KeyMap::classic_buttons")]
+    pub buttons: Vec<(GamepadButtonType, Action)>,
+    #[serde(default = "KeyMap::default_deadzone")]
+    pub stick_deadzone: f32,
+}
+
+impl Default for KeyMap {
+    fn default() -> Self {
+        KeyMap::classic()
+    }
+}
+
+impl KeyMap {
+    //冒险岛默认键位
+    pub fn classic() -> KeyMap {
+        let mut keys = BTreeMap::new();
+        keys.insert(KeyCode::Left, Action::MoveLeft);
+        keys.insert(KeyCode::Right, Action::MoveRight);
+        keys.insert(KeyCode::Up, Action::Up);
+        keys.insert(KeyCode::Down, Action::Crouch);
+        keys.insert(KeyCode::AltLeft, Action::Jump);
+        keys.insert(KeyCode::ControlLeft, Action::Attack);
+        keys.insert(KeyCode::Z, Action::Pickup);
+        let skills = [
+            KeyCode::ShiftLeft,
+            KeyCode::Insert,
+            KeyCode::Home,
+            KeyCode::PageUp,
+            KeyCode::Delete,
+            KeyCode::End,
+            KeyCode::PageDown,
+        ];
+        for (slot, key) in skills.into_iter().enumerate() {
+            keys.insert(key, Action::Skill(slot as u8));
+        }
+        KeyMap {
+            keys,
+            buttons: KeyMap::classic_buttons(),
+            stick_deadzone: STICK_DEADZONE,
+        }
+    }
+
+    pub fn classic_buttons() -> Vec<(GamepadButtonType, Action)> {
+        vec![
+            (GamepadButtonType::DPadLeft, Action::MoveLeft),
+            (GamepadButtonType::DPadRight, Action::MoveRight),
+            (GamepadButtonType::DPadUp, Action::Up),
+            (GamepadButtonType::DPadDown, Action::Crouch),
+            (GamepadButtonType::South, Action::Jump),
+            (GamepadButtonType::West, Action::Attack),
+            (GamepadButtonType::East, Action::Pickup),
+            (GamepadButtonType::North, Action::Skill(0)),
+            (GamepadButtonType::LeftTrigger, Action::Skill(1)),
+            (GamepadButtonType::RightTrigger, Action::Skill(2)),
+        ]
+    }
+
+    fn default_deadzone() -> f32 {
+        STICK_DEADZONE
+    }
+
+    pub fn load(path: &Path) -> io::Result<KeyMap> {
+        let bytes = fs::read(path)?;
+        serde_json::from_slice(&bytes)
+            .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))
+    }
+
+    pub fn save(&self, path: &Path) -> io::Result<()> {
+        let json = serde_json::to_string_pretty(self)?;
+        fs::write(path, json)
+    }
+}
+
+//按键配置文件的位置
+#[derive(Debug, Resource, Clone)]
+pub struct KeyMapPath(pub String);
+
+impl KeyMapPath {
+    pub fn from_env() -> KeyMapPath {
+        KeyMapPath(arg_or_env("keymap", KEYMAP_ENV).unwrap_or_else(|| KEYMAP_FILE.to_string()))
+    }
+}
+
+//当前使用的手柄,断开后切换到其他已连接的手柄
+#[derive(Debug, Resource, Clone, Copy, Default)]
+pub struct ActiveGamepad(pub Option<Gamepad>);
+
+pub struct ActionPlugin;
+
+impl Plugin for ActionPlugin {
+    fn build(&self, app: &mut App) {
+        let path = KeyMapPath::from_env();
+        app.insert_resource(load_keymap(&path))
+            .insert_resource(path)
+            .init_resource::<Input<Action>>()
+            .init_resource::<ActiveGamepad>()
+            .add_systems(
+                PreUpdate,
+                (update_gamepad, update_actions).chain().after(InputSystem),
+            )
+            .add_systems(
+                Update,
+                save_keymap
+                    .run_if(resource_changed::<KeyMap>().and_then(not(resource_added::<KeyMap>()))),
+            );
+    }
+}
+
+//读取按键配置,文件不存在时使用默认键位并写入文件
+fn load_keymap(path: &KeyMapPath) -> KeyMap {
+    let file = Path::new(&path.0);
+    match KeyMap::load(file) {
+        Ok(keymap) => keymap,
+        Err(err) if err.kind() == io::ErrorKind::NotFound => {
+            let keymap = KeyMap::default();
+            if let Err(err) = keymap.save(file) {
+                warn!("failed to write keymap {}: {}", path.0, err);
+            }
+            keymap
+        }
+        Err(err) => {
+            warn!("failed to read keymap {}: {}, use default", path.0, err);
+            KeyMap::default()
+        }
+    }
+}
+
+//修改键位后保存到文件
+fn save_keymap(keymap: Res<KeyMap>, path: Res<KeyMapPath>) {
+    if let Err(err) = keymap.save(Path::new(&path.0)) {
+        warn!("failed to write keymap {}: {}", path.0, err);
+    }
+}
+
+//手柄连接或断开
+fn update_gamepad(
+    mut events: EventReader<GamepadConnectionEvent>,
+    gamepads: Res<Gamepads>,
+    mut active: ResMut<ActiveGamepad>,
+) {
+    for event in events.iter() {
+        match &event.connection {
+            GamepadConnection::Connected(info) => {
+                info!("gamepad {} connected: {}", event.gamepad.id, info.name);
+                if active.0.is_none() {
+                    active.0 = Some(event.gamepad);
+                }
+            }
+            GamepadConnection::Disconnected => {
+                info!("gamepad {} disconnected", event.gamepad.id);
+                if active.0 == Some(event.gamepad) {
+                    active.0 = gamepads.iter().find(|gamepad| *gamepad != event.gamepad);
+                }
+            }
+        }
+    }
+}
+
+//把按键和手柄转换为动作,多个按键对应同一动作时任意一个按下即可
+fn update_actions(
+    keyboard: Res<Input<KeyCode>>,
+    buttons: Res<Input<GamepadButton>>,
+    axes: Res<Axis<GamepadAxis>>,
+    gamepad: Res<ActiveGamepad>,
+    keymap: Res<KeyMap>,
+    mut actions: ResMut<Input<Action>>,
+) {
+    actions.clear();
+    let mut pressed: Vec<Action> = keymap
+        .keys
+        .iter()
+        .filter(|(key, _)| keyboard.pressed(**key))
+        .map(|(_, action)| *action)
+        .collect();
+    if let Some(gamepad) = gamepad.0 {
+        pressed.extend(
+            keymap
+                .buttons
+                .iter()
+                .filter(|(button, _)| buttons.pressed(GamepadButton::new(gamepad, *button)))
+                .map(|(_, action)| *action),
+        );
+        let axis = |axis_type| {
+            axes.get(GamepadAxis::new(gamepad, axis_type))
+                .unwrap_or(0.0)
+        };
+        let x = axis(GamepadAxisType::LeftStickX);
+        let y = axis(GamepadAxisType::LeftStickY);
+        let deadzone = keymap.stick_deadzone;
+        if x < -deadzone {
+            pressed.push(Action::MoveLeft);
+        } else if x > deadzone {
+            pressed.push(Action::MoveRight);
+        }
+        if y > deadzone {
+            pressed.push(Action::Up);
+        } else if y < -deadzone {
+            pressed.push(Action::Crouch);
+        }
+    }
+    let released: Vec<Action> = actions
+        .get_pressed()
+        .filter(|action| !pressed.contains(action))
+        .copied()
+        .collect();
+    for action in released {
+        actions.release(action);
+    }
+    for action in pressed {
+        actions.press(action);
+    }
+}
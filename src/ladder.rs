@@ -0,0 +1,107 @@
+use std::cmp::{max, min};
+
+use bevy::prelude::*;
+use serde::Deserialize;
+
+//人物与梯子/绳子的最大水平距离
+const LADDER_RANGE: f32 = 10.0;
+
+//梯子和绳子,L为1时是梯子,否则是绳子,UF为1时可以从顶端爬到上面的地砖
+#[derive(Component, Debug, Clone, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct LadderRope {
+    #[serde(rename = "ID")]
+    pub id: i32,
+    #[serde(rename = "L")]
+    pub l: i32,
+    #[serde(rename = "UF")]
+    pub uf: i32,
+    pub x: i32,
+    pub y1: i32,
+    pub y2: i32,
+    #[serde(default)]
+    pub page: i32,
+}
+
+impl LadderRope {
+    pub fn is_ladder(&self) -> bool {
+        self.l != 0
+    }
+
+    //json中y轴向下,转换为bevy坐标
+    pub fn top(&self) -> f32 {
+        -min(self.y1, self.y2) as f32
+    }
+
+    pub fn bottom(&self) -> f32 {
+        -max(self.y1, self.y2) as f32
+    }
+
+    //p为人物脚下的位置,按上键时能否抓住
+    pub fn can_grab(&self, p: Vec2) -> bool {
+        (p.x - self.x as f32).abs() <= LADDER_RANGE && p.y >= self.bottom() && p.y < self.top()
+    }
+
+    //站在顶端按下键时能否抓住
+    pub fn can_grab_from_top(&self, p: Vec2) -> bool {
+        (p.x - self.x as f32).abs() <= LADDER_RANGE && (p.y - self.top()).abs() <= LADDER_RANGE
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bevy::prelude::Vec2;
+
+    use crate::map::MapData;
+
+    //json中y轴向下,梯子在bevy坐标中从y=50到y=200,绳子从y=-40到y=-10
+    const MAP: &str = r#"{"ID": 0, "Layers": [], "LadderRope": [
+        {"ID": 1, "L": 1, "UF": 1, "X": 100, "Y1": -200, "Y2": -50, "Page": 2},
+        {"ID": 2, "L": 0, "UF": 0, "X": -30, "Y1": 40, "Y2": 10}
+    ]}"#;
+
+    #[test]
+    fn ladder_rope_is_parsed_from_map() {
+        let map = MapData::from_slice(MAP.as_bytes()).unwrap();
+        let [ladder, rope] = &map.ladder_rope[..] else {
+            panic!("expected 2 ladders, got {:?}", map.ladder_rope);
+        };
+        assert_eq!(
+            (ladder.id, ladder.x, ladder.uf, ladder.page),
+            (1, 100, 1, 2)
+        );
+        assert!(ladder.is_ladder());
+        assert_eq!((ladder.top(), ladder.bottom()), (200.0, 50.0));
+        //没有Page时为0
+        assert_eq!((rope.id, rope.uf, rope.page), (2, 0, 0));
+        assert!(!rope.is_ladder());
+        assert_eq!((rope.top(), rope.bottom()), (-10.0, -40.0));
+    }
+
+    #[test]
+    fn can_grab_within_range() {
+        let map = MapData::from_slice(MAP.as_bytes()).unwrap();
+        let ladder = &map.ladder_rope[0];
+        assert!(ladder.can_grab(Vec2::new(100.0, 50.0)));
+        assert!(ladder.can_grab(Vec2::new(110.0, 120.0)));
+        assert!(ladder.can_grab(Vec2::new(90.0, 199.0)));
+        //水平距离超过10
+        assert!(!ladder.can_grab(Vec2::new(111.0, 120.0)));
+        //低于底端或已经到达顶端
+        assert!(!ladder.can_grab(Vec2::new(100.0, 49.0)));
+        assert!(!ladder.can_grab(Vec2::new(100.0, 200.0)));
+    }
+
+    #[test]
+    fn can_grab_from_top_near_the_top() {
+        let map = MapData::from_slice(MAP.as_bytes()).unwrap();
+        let ladder = &map.ladder_rope[0];
+        assert!(ladder.can_grab_from_top(Vec2::new(100.0, 200.0)));
+        assert!(ladder.can_grab_from_top(Vec2::new(95.0, 210.0)));
+        assert!(ladder.can_grab_from_top(Vec2::new(110.0, 190.0)));
+        assert!(!ladder.can_grab_from_top(Vec2::new(100.0, 189.0)));
+        assert!(!ladder.can_grab_from_top(Vec2::new(89.0, 200.0)));
+        //站在底端时不能向下抓住
+        assert!(!ladder.can_grab_from_top(Vec2::new(100.0, 50.0)));
+    }
+}
@@ -0,0 +1,964 @@
+use std::{
+    cmp::{max, min},
+    fmt, fs,
+    path::Path,
+};
+
+use bevy::{
+    asset::{AssetLoader, LoadContext, LoadState, LoadedAsset},
+    prelude::*,
+    reflect::{TypePath, TypeUuid},
+    utils::BoxedFuture,
+};
+use bevy_rapier2d::prelude::*;
+use serde::Deserialize;
+
+use crate::{
+    animate::{AnimationFrame, Animations},
+    background::{BackGround, BackGroundEdge},
+    foothold::{FootHold, FootHoldType, FootholdGraph, FLOOR_GROUP},
+    ladder::LadderRope,
+    utils::{arg_or_env, cal_ax, cal_ay, composite_zindex},
+};
+
+//默认加载的地图,路径相对于assets目录
+const DEFAULT_MAP: &str = "Map/Map/Map0/000010000.json";
+//传送门类型0为出生点
+const PORTAL_SPAWN: i32 = 0;
+const PORTAL_NO_MAP: i64 = 999999999;
+//按上键时人物与传送门的最大距离
+const PORTAL_RANGE: Vec2 = Vec2::new(25.0, 50.0);
+//地图json所在目录,按MapN子目录存放
+const MAP_DIR: &str = "assets/Map/Map";
+//未通过命令行指定地图时读取的环境变量
+const MAP_ENV: &str = "STUDYMS_MAP";
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash, States)]
+pub enum MapState {
+    #[default]
+    Setup,
+    Loading,
+    Loaded,
+    Failed,
+}
+
+//当前地图的句柄
+#[derive(Debug, Resource)]
+pub struct MapHandle(pub Handle<MapData>);
+
+//属于当前地图的实体,切换地图时全部销毁
+#[derive(Debug, Component, Clone, Copy, Default)]
+pub struct MapEntity;
+
+//切换地图后人物出现的位置
+#[derive(Debug, Clone, Default, PartialEq)]
+pub enum SpawnPoint {
+    #[default]
+    Default,
+    //目标地图中的传送门名字
+    Portal(String),
+}
+
+//切换地图,path为assets下的地图json路径
+#[derive(Debug, Clone, Event)]
+pub struct ChangeMap {
+    pub path: String,
+    pub spawn: SpawnPoint,
+}
+
+//地图加载完成后人物出现的位置
+#[derive(Debug, Resource, Clone, Default)]
+pub struct PlayerSpawn(pub SpawnPoint);
+
+//启动时加载的地图,可以用 --map <地图ID或路径> 或环境变量STUDYMS_MAP指定
+#[derive(Debug, Resource, Clone)]
+pub struct MapConfig {
+    pub path: String,
+}
+
+impl MapConfig {
+    pub fn from_env() -> MapConfig {
+        let path = arg_or_env("map", MAP_ENV)
+            .and_then(|map| {
+                let path = map_path(&map);
+                if path.is_none() {
+                    warn!("unknown map {:?}, fall back to {}", map, DEFAULT_MAP);
+                }
+                path
+            })
+            .unwrap_or_else(|| DEFAULT_MAP.to_string());
+        MapConfig { path }
+    }
+}
+
+//把地图ID或json路径转换为assets下的路径,例如 10000 -> Map/Map/Map0/000010000.json
+pub fn map_path(map: &str) -> Option<String> {
+    if map.ends_with(".json") {
+        let path = map.trim_start_matches("./");
+        let path = path.strip_prefix("assets/").unwrap_or(path);
+        //路径写错时和找不到的ID一样返回None
+        if !Path::new("assets").join(path).is_file() {
+            return None;
+        }
+        return Some(path.to_string());
+    }
+    let id: i64 = map.parse().ok()?;
+    let file = format!("{:09}.json", id);
+    //地图不一定按ID放在对应的MapN目录,依次查找
+    for dir in fs::read_dir(MAP_DIR).ok()?.flatten() {
+        if dir.path().join(&file).is_file() {
+            let dir = dir.file_name();
+            return Some(format!("Map/Map/{}/{}", dir.to_string_lossy(), file));
+        }
+    }
+    None
+}
+
+pub struct MapPlugin;
+
+impl Plugin for MapPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_asset::<MapData>()
+            .init_asset_loader::<MapAssetLoader>()
+            .insert_resource(MapConfig::from_env())
+            .init_resource::<PlayerSpawn>()
+            .add_state::<MapState>()
+            .add_systems(OnEnter(MapState::Setup), setup_map)
+            .add_systems(
+                Update,
+                check_map.run_if(in_state(MapState::Loading)), //等待地图读取完成
+            )
+            .add_systems(OnEnter(MapState::Loaded), spawn_map) //生成地图
+            .add_systems(Update, change_map.run_if(on_event::<ChangeMap>()))
+            .add_event::<ChangeMap>();
+    }
+}
+
+//地图json的结构,json文件参考 https://github.com/Kagamia/MapRenderWeb.git
+#[derive(Debug, Clone, Deserialize, TypeUuid, TypePath)]
+#[uuid = "5b0f1c39-7d44-4a5e-9a51-2f4b8d3c6e10"]
+#[serde(rename_all = "PascalCase")]
+pub struct MapData {
+    #[serde(rename = "ID")]
+    pub id: i32,
+    pub layers: Vec<Layer>,
+    #[serde(default)]
+    pub backs: Vec<Back>,
+    //部分地图没有FootHold
+    #[serde(default)]
+    pub foot_hold: Vec<FootHold>,
+    #[serde(default)]
+    pub portals: Vec<Portal>,
+    #[serde(default)]
+    pub ladder_rope: Vec<LadderRope>,
+}
+
+//i相当于layer,越大的layer会覆盖较小layer的物体,Tiles和Objs可能为null
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct Layer {
+    #[serde(default)]
+    pub tiles: Option<Vec<Tile>>,
+    #[serde(default)]
+    pub objs: Option<Vec<Obj>>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct Tile {
+    #[serde(rename = "ID")]
+    pub id: i32,
+    pub x: i32,
+    pub y: i32,
+    pub resource: SpriteResource,
+}
+
+//单张图片资源,Tile和Back共用
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct SpriteResource {
+    pub width: i32,
+    pub height: i32,
+    pub origin_x: i32,
+    pub origin_y: i32,
+    pub z: i32,
+    pub resource_url: String,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct Obj {
+    #[serde(rename = "ID")]
+    pub id: i32,
+    pub x: i32,
+    pub y: i32,
+    pub z: i32,
+    pub flip_x: bool,
+    pub resource: ObjResource,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct ObjResource {
+    pub frames: Vec<Frame>,
+}
+
+//obj动画的一帧,A0/A1为该帧起止透明度
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct Frame {
+    pub width: i32,
+    pub height: i32,
+    pub origin_x: i32,
+    pub origin_y: i32,
+    pub z: i32,
+    pub delay: i32,
+    pub a0: i32,
+    pub a1: i32,
+    pub resource_url: String,
+}
+
+impl Frame {
+    pub fn sprite(&self) -> SpriteResource {
+        SpriteResource {
+            width: self.width,
+            height: self.height,
+            origin_x: self.origin_x,
+            origin_y: self.origin_y,
+            z: self.z,
+            resource_url: self.resource_url.clone(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct Back {
+    #[serde(rename = "ID")]
+    pub id: i32,
+    pub x: i32,
+    pub y: i32,
+    pub cx: i32,
+    pub cy: i32,
+    pub rx: i32,
+    pub ry: i32,
+    pub alpha: i32,
+    pub flip_x: bool,
+    pub front: bool,
+    pub ani: i32,
+    #[serde(rename = "Type")]
+    pub types: i32,
+    //Resource的格式由Ani决定,MapData::from_slice按Ani解析到resource
+    #[serde(default, rename = "Resource")]
+    pub raw_resource: Option<serde_json::Value>,
+    #[serde(skip)]
+    pub resource: Option<BackResource>,
+}
+
+impl Back {
+    //Ani为0时是单张图片,为1时和obj一样有多帧
+    //其他格式(例如Ani=2的spine动画)不解析,不影响整张地图的解析
+    fn parse_resource(&mut self) -> Result<(), serde_path_to_error::Error<serde_json::Error>> {
+        let Some(raw) = self.raw_resource.take() else {
+            return Ok(());
+        };
+        let resource = match self.ani {
+            0 => BackResource::Sprite(serde_path_to_error::deserialize(raw)?),
+            1 => BackResource::Animated(serde_path_to_error::deserialize(raw)?),
+            _ => return Ok(()),
+        };
+        self.resource = Some(resource);
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone)]
+pub enum BackResource {
+    Sprite(SpriteResource),
+    Animated(ObjResource),
+}
+
+//传送门,ToMap为999999999时没有目标地图
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct Portal {
+    #[serde(rename = "ID")]
+    pub id: i32,
+    pub name: String,
+    #[serde(rename = "Type")]
+    pub types: i32,
+    pub x: i32,
+    pub y: i32,
+    pub to_map: i64,
+    pub to_name: String,
+    //隐藏的传送门没有图片
+    #[serde(default)]
+    pub resource: Option<ObjResource>,
+}
+
+impl Portal {
+    pub fn position(&self) -> Vec2 {
+        Vec2::new(self.x as f32, -self.y as f32)
+    }
+
+    //出生点和没有目标地图的传送门不能进入
+    pub fn is_usable(&self) -> bool {
+        self.types != PORTAL_SPAWN && self.to_map != PORTAL_NO_MAP
+    }
+}
+
+//解析地图失败,path为出错字段的路径,例如Layers[2].Objs[0].Resource.Frames[1].Delay
+#[derive(Debug)]
+pub struct MapError {
+    pub path: String,
+    pub source: serde_json::Error,
+}
+
+impl MapError {
+    //prefix为被解析部分在地图json中的路径
+    fn new(prefix: &str, err: serde_path_to_error::Error<serde_json::Error>) -> MapError {
+        let path = err.path().to_string();
+        let path = match (prefix, path.as_str()) {
+            ("", _) => path,
+            (_, ".") => prefix.to_string(),
+            _ => format!("{}.{}", prefix, path),
+        };
+        MapError {
+            path,
+            source: err.into_inner(),
+        }
+    }
+}
+
+impl fmt::Display for MapError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "invalid map data at `{}`: {}", self.path, self.source)
+    }
+}
+
+impl std::error::Error for MapError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        Some(&self.source)
+    }
+}
+
+impl MapData {
+    //人物出生点
+    pub fn spawn_position(&self, spawn: &SpawnPoint) -> Vec2 {
+        let portal = match spawn {
+            SpawnPoint::Default => self.portals.iter().find(|p| p.types == PORTAL_SPAWN),
+            SpawnPoint::Portal(name) => self.portal(name),
+        };
+        portal.map(|p| p.position()).unwrap_or(Vec2::ZERO)
+    }
+
+    pub fn portal(&self, name: &str) -> Option<&Portal> {
+        self.portals.iter().find(|p| p.name == name)
+    }
+
+    //人物脚下位置附近可以进入的传送门
+    pub fn portal_at(&self, p: Vec2) -> Option<&Portal> {
+        self.portals.iter().find(|portal| {
+            let position = portal.position();
+            portal.is_usable()
+                && (p.x - position.x).abs() <= PORTAL_RANGE.x
+                && (p.y - position.y).abs() <= PORTAL_RANGE.y
+        })
+    }
+
+    pub fn from_slice(bytes: &[u8]) -> Result<MapData, MapError> {
+        let de = &mut serde_json::Deserializer::from_slice(bytes);
+        let mut map: MapData =
+            serde_path_to_error::deserialize(de).map_err(|err| MapError::new("", err))?;
+        for (i, back) in map.backs.iter_mut().enumerate() {
+            back.parse_resource()
+                .map_err(|err| MapError::new(&format!("Backs[{}].Resource", i), err))?;
+        }
+        Ok(map)
+    }
+}
+
+//通过AssetServer异步读取地图json
+#[derive(Default)]
+pub struct MapAssetLoader;
+
+impl AssetLoader for MapAssetLoader {
+    fn load<'a>(
+        &'a self,
+        bytes: &'a [u8],
+        load_context: &'a mut LoadContext,
+    ) -> BoxedFuture<'a, Result<(), bevy::asset::Error>> {
+        Box::pin(async move {
+            let map = MapData::from_slice(bytes)?;
+            load_context.set_default_asset(LoadedAsset::new(map));
+            Ok(())
+        })
+    }
+
+    fn extensions(&self) -> &[&str] {
+        &["json"]
+    }
+}
+
+fn setup_map(
+    mut commands: Commands,
+    asset_server: Res<AssetServer>,
+    config: Res<MapConfig>,
+    mut next_state: ResMut<NextState<MapState>>,
+) {
+    //读取地图json,参考https://www.bilibili.com/video/BV1ou4y1o7XZ/
+    info!("load map {}", config.path);
+    commands.insert_resource(MapHandle(asset_server.load(config.path.as_str())));
+    next_state.set(MapState::Loading);
+}
+
+//等待地图加载完成
+fn check_map(
+    mut next_state: ResMut<NextState<MapState>>,
+    map: Res<MapHandle>,
+    asset_server: Res<AssetServer>,
+) {
+    match asset_server.get_load_state(&map.0) {
+        LoadState::Loaded => next_state.set(MapState::Loaded),
+        LoadState::Failed => {
+            error!(
+                "failed to load map {:?}",
+                asset_server.get_handle_path(&map.0)
+            );
+            next_state.set(MapState::Failed);
+        }
+        _ => {}
+    }
+}
+
+//销毁当前地图的所有实体,然后读取新地图
+fn change_map(
+    mut commands: Commands,
+    mut events: EventReader<ChangeMap>,
+    asset_server: Res<AssetServer>,
+    query: Query<Entity, With<MapEntity>>,
+    mut next_state: ResMut<NextState<MapState>>,
+) {
+    //同一帧有多个切换请求时只处理最后一个
+    let Some(event) = events.iter().last() else {
+        return;
+    };
+    for entity in query.iter() {
+        commands.entity(entity).despawn_recursive();
+    }
+    commands.remove_resource::<BackGroundEdge>();
+    commands.remove_resource::<FootholdGraph>();
+    info!("change map to {}", event.path);
+    commands.insert_resource(MapHandle(asset_server.load(event.path.as_str())));
+    commands.insert_resource(PlayerSpawn(event.spawn.clone()));
+    next_state.set(MapState::Loading);
+}
+
+fn spawn_map(
+    mut commands: Commands,
+    asset_server: Res<AssetServer>,
+    maps: Res<Assets<MapData>>,
+    map: Res<MapHandle>,
+) {
+    let Some(res) = maps.get(&map.0) else {
+        return;
+    };
+
+    //解析背景的json文件,从Layer开始
+    //i相当于layer,越大的i会覆盖较小的i值的物体
+    for (i, layer) in res.layers.iter().enumerate() {
+        for obj in layer.objs.iter().flatten() {
+            let x = obj.x as f32;
+            let y = -obj.y as f32;
+            //根据 z,id,i计算z值
+            let z = composite_zindex(i as i128, obj.z as i128, obj.id as i128, 0);
+
+            //具有动画效果的obj
+            spawn_animation(
+                &mut commands,
+                &asset_server,
+                &obj.resource.frames,
+                x,
+                y,
+                z,
+                obj.flip_x,
+            );
+        }
+        //从地图json解析Tiles
+        for tile in layer.tiles.iter().flatten() {
+            let x = tile.x as f32;
+            let y = -tile.y as f32;
+            let z = composite_zindex(i as i128, tile.resource.z as i128, tile.id as i128, 0);
+
+            let ox = cal_ax(tile.resource.origin_x as f32, tile.resource.width as f32);
+            let oy = -cal_ay(tile.resource.origin_y as f32, tile.resource.height as f32);
+
+            commands.spawn((
+                SpriteBundle {
+                    texture: asset_server.load(&tile.resource.resource_url),
+                    transform: Transform::from_xyz(x, y, z),
+                    sprite: Sprite {
+                        anchor: bevy::sprite::Anchor::Custom(Vec2::new(ox, oy)),
+                        ..default()
+                    },
+                    ..default()
+                },
+                MapEntity,
+            ));
+        }
+    }
+    //传送门画在所有layer之上
+    for portal in &res.portals {
+        if let Some(resource) = &portal.resource {
+            let position = portal.position();
+            let z = composite_zindex(7, 8, portal.id as i128, 0);
+            spawn_animation(
+                &mut commands,
+                &asset_server,
+                &resource.frames,
+                position.x,
+                position.y,
+                z,
+                false,
+            );
+        }
+    }
+    //梯子和绳子
+    for ladder in &res.ladder_rope {
+        commands.spawn((ladder.clone(), MapEntity));
+    }
+    //解析地图Backs
+    for back in &res.backs {
+        //第一帧作为背景的图片,动画背景之后由animate_background切换
+        let (frames, delays) = match &back.resource {
+            Some(BackResource::Sprite(sprite)) => (vec![sprite.clone()], vec![0.0]),
+            Some(BackResource::Animated(ani)) if !ani.frames.is_empty() => (
+                ani.frames.iter().map(Frame::sprite).collect(),
+                ani.frames
+                    .iter()
+                    .map(|frame| frame.delay as f32 / 1000.0)
+                    .collect(),
+            ),
+            _ => {
+                warn!("skip back {} with unsupported Ani {}", back.id, back.ani);
+                continue;
+            }
+        };
+        let background = BackGround::new(
+            back.id,
+            back.x,
+            -back.y,
+            back.cx,
+            back.cy,
+            back.rx,
+            back.ry,
+            back.alpha,
+            back.flip_x,
+            back.front,
+            back.ani,
+            back.types,
+            frames[0].clone(),
+        )
+        .with_frames(frames, delays);
+        commands.spawn((background, MapEntity));
+    }
+    //解析地图FootHold
+    /*
+    线段绘制方向决定了单边碰撞的方向
+    水平线段如果是从左往右画，那么从上往下移动会发生碰撞，从下往上移动不会发生碰撞--GruopA
+    水平线段如果是从右往左画，那么从下往上移动会发生碰撞，从上往下移动不会发生碰撞--GruopB
+    垂直线段如果是从上往下画，那么从右往左移动会发生碰撞，从左往右移动不会发生碰撞--GruopC
+    垂直线段如果是从下往上画，那么从左往右移动会发生碰撞，从右往左移动不会发生碰撞--GruopD
+    所有斜线只有从上往下会发生碰撞
+
+    当某条垂直线段往下延伸的其他线段都是垂直线段，且没有出现拐弯就突然中断时，那么这条线段是无效的墙，永远不会发生碰撞
+
+    当角色处于地面的时候，只能与相同layer的线段发生碰撞
+    当角色处于空中的时候，能与所有非垂直线段发生碰撞，但是只能与相同layer的垂直线段发生碰撞
+     */
+    let graph = FootholdGraph::new(&res.foot_hold);
+    if !res.foot_hold.is_empty() {
+        let mut left = 0;
+        let mut right = 0;
+        for foothold in &res.foot_hold {
+            let foothold = foothold.clone();
+            if left > min(foothold.x1, foothold.x2) {
+                left = min(foothold.x1, foothold.x2)
+            }
+            if right < max(foothold.x1, foothold.x2) {
+                right = max(foothold.x1, foothold.x2)
+            }
+            commands.insert_resource(BackGroundEdge {
+                left: left as f32,
+                right: right as f32,
+            });
+            //无效的墙不生成碰撞体
+            if graph.is_invalid_wall(foothold.id) {
+                continue;
+            }
+            // commands.spawn(foothold);
+            //非垂直的地砖额外属于FLOOR_GROUP
+            let mut memberships = FootHold::get_foothold_layer(foothold.layer);
+            if !foothold.is_wall() {
+                memberships |= FLOOR_GROUP;
+            }
+            //直接用bevy_rapier2d生成地砖,使其具有物理效果
+            commands.spawn((
+                Collider::segment(
+                    Vec2::new(foothold.x1 as f32, -foothold.y1 as f32),
+                    Vec2::new(foothold.x2 as f32, -foothold.y2 as f32),
+                ),
+                CollisionGroups::new(
+                    memberships,
+                    FootHold::get_foothold_group(
+                        Vec2::new(foothold.x1 as f32, -foothold.y1 as f32),
+                        Vec2::new(foothold.x2 as f32, -foothold.y2 as f32),
+                    ),
+                ),
+                FootHold::get_foothold_type(
+                    Vec2::new(foothold.x1 as f32, -foothold.y1 as f32),
+                    Vec2::new(foothold.x2 as f32, -foothold.y2 as f32),
+                ),
+                RigidBody::Fixed,
+                foothold,
+                MapEntity,
+            ));
+        }
+        //地图左边墙壁
+        commands.spawn((
+            Collider::segment(
+                Vec2::new(left as f32, -10000.0),
+                Vec2::new(left as f32, 10000.0),
+            ),
+            RigidBody::Fixed,
+            FootHoldType::Vertical,
+            CollisionGroups::new(Group::ALL, Group::ALL),
+            FootHold {
+                x1: left,
+                x2: left,
+                y1: 10000,
+                y2: 10000,
+                prev: -1,
+                next: -1,
+                piece: -1,
+                layer: -1,
+                id: -1,
+            },
+            MapEntity,
+        ));
+        //地图右边墙壁
+        commands.spawn((
+            Collider::segment(
+                Vec2::new(right as f32, -10000.0),
+                Vec2::new(right as f32, 10000.0),
+            ),
+            RigidBody::Fixed,
+            FootHoldType::Vertical,
+            CollisionGroups::new(Group::ALL, Group::ALL),
+            FootHold {
+                x1: right,
+                x2: right,
+                y1: 10000,
+                y2: 10000,
+                prev: -1,
+                next: -1,
+                piece: -1,
+                layer: -1,
+                id: -1,
+            },
+            MapEntity,
+        ));
+    }
+    commands.insert_resource(graph);
+}
+
+//生成obj实体,多帧时由animate_back在同一个实体上切换图片
+fn spawn_animation(
+    commands: &mut Commands,
+    asset_server: &AssetServer,
+    frames: &[Frame],
+    x: f32,
+    y: f32,
+    z: f32,
+    flip_x: bool,
+) {
+    let frames: Vec<AnimationFrame> = frames
+        .iter()
+        .map(|frame| {
+            //计算物体原点坐标
+            let mut ox = cal_ax(frame.origin_x as f32, frame.width as f32);
+            let oy = -cal_ay(frame.origin_y as f32, frame.height as f32);
+            //翻转后原点也要翻转
+            if flip_x {
+                ox = -ox;
+            }
+            AnimationFrame {
+                texture: asset_server.load(&frame.resource_url),
+                anchor: Vec2::new(ox, oy),
+                delay: frame.delay as f32 / 1000.0,
+                alpha: (frame.a0 as f32, frame.a1 as f32),
+            }
+        })
+        .collect();
+    let Some(first) = frames.first() else {
+        return;
+    };
+    let sprite = SpriteBundle {
+        texture: first.texture.clone(),
+        transform: Transform::from_xyz(x, y, z),
+        sprite: Sprite {
+            anchor: bevy::sprite::Anchor::Custom(first.anchor),
+            flip_x,
+            color: Color::rgba(1.0, 1.0, 1.0, first.alpha.0 / 255.0),
+            ..default()
+        },
+        ..default()
+    };
+    commands.spawn((sprite, Animations::new(frames), MapEntity));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn back_json(ani: i32, resource: &str) -> String {
+        format!(
+            r#"{{"ID": 0, "Layers": [], "Backs": [{{"ID": 3, "X": 0, "Y": 0, "Cx": 0, "Cy": 0,
+            "Rx": 0, "Ry": 0, "Alpha": 255, "FlipX": false, "Front": false, "Ani": {},
+            "Type": 0, "Resource": {}}}]}}"#,
+            ani, resource
+        )
+    }
+
+    #[test]
+    fn back_resource_is_parsed_by_ani() {
+        let sprite = r#"{"Width": 22, "Height": 738, "OriginX": 11, "OriginY": 369, "Z": 0,
+            "ResourceUrl": "Map/Back/grassySoil_new.img/back/0.png"}"#;
+        let map = MapData::from_slice(back_json(0, sprite).as_bytes()).unwrap();
+        assert!(matches!(
+            &map.backs[0].resource,
+            Some(BackResource::Sprite(sprite)) if sprite.width == 22
+        ));
+
+        let animated = r#"{"Frames": [{"Width": 1, "Height": 1, "OriginX": 0, "OriginY": 0,
+            "Z": 0, "Delay": 100, "A0": 255, "A1": 0, "ResourceUrl": "0.png"}]}"#;
+        let map = MapData::from_slice(back_json(1, animated).as_bytes()).unwrap();
+        assert!(matches!(
+            &map.backs[0].resource,
+            Some(BackResource::Animated(ani)) if ani.frames.len() == 1
+        ));
+
+        //未知的Ani不解析Resource
+        let spine = r#"{"Atlas": "back.atlas", "Skeleton": 7}"#;
+        let map = MapData::from_slice(back_json(2, spine).as_bytes()).unwrap();
+        assert!(map.backs[0].resource.is_none());
+    }
+
+    #[test]
+    fn shipped_maps_parse_every_back() {
+        for path in ["000010000.json", "1000010000.json"] {
+            let bytes = fs::read(format!("{}/Map0/{}", MAP_DIR, path)).unwrap();
+            let map = MapData::from_slice(&bytes).unwrap();
+            assert!(!map.backs.is_empty());
+            assert!(map.backs.iter().all(|back| back.resource.is_some()));
+        }
+    }
+
+    #[test]
+    fn invalid_back_resource_names_the_field() {
+        //Ani=0的图片缺少Width
+        let missing = r#"{"Height": 738, "OriginX": 11, "OriginY": 369, "Z": 0,
+            "ResourceUrl": "0.png"}"#;
+        let err = MapData::from_slice(back_json(0, missing).as_bytes()).unwrap_err();
+        assert_eq!(err.path, "Backs[0].Resource");
+        assert!(err.to_string().contains("missing field `Width`"));
+
+        let animated = r#"{"Frames": [{"Width": 1, "Height": 1, "OriginX": 0, "OriginY": 0,
+            "Z": 0, "Delay": "100", "A0": 255, "A1": 0, "ResourceUrl": "0.png"}]}"#;
+        let err = MapData::from_slice(back_json(1, animated).as_bytes()).unwrap_err();
+        assert_eq!(err.path, "Backs[0].Resource.Frames[0].Delay");
+
+        //单张图片的Resource不能当作动画解析
+        let sprite = r#"{"Width": 22, "Height": 738, "OriginX": 11, "OriginY": 369, "Z": 0,
+            "ResourceUrl": "0.png"}"#;
+        let err = MapData::from_slice(back_json(1, sprite).as_bytes()).unwrap_err();
+        assert_eq!(err.path, "Backs[0].Resource");
+    }
+
+    #[test]
+    fn invalid_map_names_the_field() {
+        let json = r#"{"ID": 0, "Layers": [{"Tiles": null, "Objs": null},
+            {"Tiles": [{"ID": 0, "X": "12", "Y": 0, "Resource": {}}]}]}"#;
+        let err = MapData::from_slice(json.as_bytes()).unwrap_err();
+        assert_eq!(err.path, "Layers[1].Tiles[0].X");
+        assert!(err
+            .to_string()
+            .starts_with("invalid map data at `Layers[1].Tiles[0].X`"));
+    }
+
+    //带传送门的地图,字段名和MapRenderWeb导出的json相同,y轴向下
+    const PORTAL_MAP: &str = r#"{"ID": 10000, "Layers": [], "Portals": [
+        {"ID": 0, "Name": "sp", "Type": 0, "X": -200, "Y": 100, "ToMap": 999999999, "ToName": ""},
+        {"ID": 1, "Name": "east00", "Type": 2, "X": 300, "Y": 50, "ToMap": 1000010000,
+            "ToName": "west00",
+            "Resource": {"Frames": [{"Width": 89, "Height": 257, "OriginX": 46, "OriginY": 252,
+                "Z": 0, "Delay": 100, "A0": 255, "A1": 255, "ResourceUrl": "Map/MapHelper.img/portal/game/pv/0.png"}]}},
+        {"ID": 2, "Name": "west00", "Type": 2, "X": -300, "Y": 50, "ToMap": 10000, "ToName": "east00"},
+        {"ID": 3, "Name": "tp", "Type": 1, "X": 0, "Y": 0, "ToMap": 999999999, "ToName": ""}
+    ]}"#;
+
+    #[test]
+    fn portals_are_parsed() {
+        let map = MapData::from_slice(PORTAL_MAP.as_bytes()).unwrap();
+        assert_eq!(map.portals.len(), 4);
+        let east = map.portal("east00").unwrap();
+        assert_eq!((east.id, east.types, east.x, east.y), (1, 2, 300, 50));
+        assert_eq!((east.to_map, east.to_name.as_str()), (1000010000, "west00"));
+        assert_eq!(east.resource.as_ref().unwrap().frames.len(), 1);
+        //目标地图ID转换为assets下的路径
+        assert_eq!(
+            map_path(&east.to_map.to_string()).as_deref(),
+            Some("Map/Map/Map0/1000010000.json")
+        );
+        //隐藏的传送门没有图片
+        assert!(map.portal("west00").unwrap().resource.is_none());
+        assert_eq!(east.position(), Vec2::new(300.0, -50.0));
+        assert!(map.portal("missing").is_none());
+    }
+
+    #[test]
+    fn portal_at_checks_range_and_usability() {
+        let map = MapData::from_slice(PORTAL_MAP.as_bytes()).unwrap();
+        let name = |p: Vec2| map.portal_at(p).map(|portal| portal.name.as_str());
+        assert_eq!(name(Vec2::new(300.0, -50.0)), Some("east00"));
+        assert_eq!(name(Vec2::new(325.0, 0.0)), Some("east00"));
+        assert_eq!(name(Vec2::new(275.0, -100.0)), Some("east00"));
+        assert_eq!(name(Vec2::new(326.0, -50.0)), None);
+        assert_eq!(name(Vec2::new(300.0, 1.0)), None);
+        assert_eq!(name(Vec2::new(-300.0, -50.0)), Some("west00"));
+        //出生点和没有目标地图的传送门不能进入
+        assert_eq!(name(Vec2::new(-200.0, -100.0)), None);
+        assert_eq!(name(Vec2::new(0.0, 0.0)), None);
+    }
+
+    #[test]
+    fn spawn_position_uses_portals() {
+        let map = MapData::from_slice(PORTAL_MAP.as_bytes()).unwrap();
+        assert_eq!(
+            map.spawn_position(&SpawnPoint::Default),
+            Vec2::new(-200.0, -100.0)
+        );
+        assert_eq!(
+            map.spawn_position(&SpawnPoint::Portal("west00".to_string())),
+            Vec2::new(-300.0, -50.0)
+        );
+        //找不到传送门时使用原点
+        assert_eq!(
+            map.spawn_position(&SpawnPoint::Portal("missing".to_string())),
+            Vec2::ZERO
+        );
+        let empty = MapData::from_slice(br#"{"ID": 0, "Layers": []}"#).unwrap();
+        assert_eq!(empty.spawn_position(&SpawnPoint::Default), Vec2::ZERO);
+    }
+
+    #[test]
+    fn map_path_resolves_ids_and_paths() {
+        assert_eq!(
+            map_path("10000").as_deref(),
+            Some("Map/Map/Map0/000010000.json")
+        );
+        assert_eq!(
+            map_path("000010000").as_deref(),
+            Some("Map/Map/Map0/000010000.json")
+        );
+        //地图不一定在ID对应的MapN目录中
+        assert_eq!(
+            map_path("1000010000").as_deref(),
+            Some("Map/Map/Map0/1000010000.json")
+        );
+        assert_eq!(
+            map_path("./assets/Map/Map/Map0/000010000.json").as_deref(),
+            Some("Map/Map/Map0/000010000.json")
+        );
+        assert_eq!(map_path("999999999"), None);
+        assert_eq!(map_path("Map/Map/Map0/999999999.json"), None);
+        assert_eq!(map_path("henesys"), None);
+    }
+
+    fn count<F: bevy::ecs::query::ReadOnlyWorldQuery>(app: &mut App) -> usize {
+        app.world.query_filtered::<(), F>().iter(&app.world).count()
+    }
+
+    //切换地图时销毁地砖、obj动画、背景和背景图片、碰撞体,并删除地图资源,人物保留
+    #[test]
+    fn change_map_despawns_every_map_entity() {
+        use crate::background::{BackEnity, BackGroundPlugin, BackTiles};
+        use bevy::window::PrimaryWindow;
+
+        let mut app = App::new();
+        app.add_plugins((MinimalPlugins, AssetPlugin::default(), BackGroundPlugin))
+            .add_asset::<MapData>()
+            .init_asset_loader::<MapAssetLoader>()
+            .init_resource::<PlayerSpawn>()
+            .add_state::<MapState>()
+            .add_event::<ChangeMap>()
+            .add_systems(OnEnter(MapState::Loaded), spawn_map)
+            .add_systems(Update, change_map.run_if(on_event::<ChangeMap>()));
+        app.world.spawn((
+            Window {
+                resolution: (800.0, 600.0).into(),
+                ..default()
+            },
+            PrimaryWindow,
+        ));
+        app.world.spawn((Camera::default(), Transform::default()));
+        let player = app.world.spawn(Transform::default()).id();
+
+        let bytes = fs::read(format!("{}/Map0/000010000.json", MAP_DIR)).unwrap();
+        let map = MapData::from_slice(&bytes).unwrap();
+        let handle = app.world.resource_mut::<Assets<MapData>>().add(map);
+        app.insert_resource(MapHandle(handle));
+        app.world
+            .resource_mut::<NextState<MapState>>()
+            .set(MapState::Loaded);
+        app.update();
+        //背景图片在下一帧生成
+        app.update();
+
+        //地砖
+        assert!(count::<(With<Sprite>, Without<BackEnity>, Without<Animations>)>(&mut app) > 0);
+        assert!(count::<With<Animations>>(&mut app) > 0);
+        assert!(count::<With<BackGround>>(&mut app) > 0);
+        assert!(count::<With<BackTiles>>(&mut app) > 0);
+        assert!(count::<With<BackEnity>>(&mut app) > 0);
+        assert!(count::<With<Collider>>(&mut app) > 0);
+        assert!(app.world.contains_resource::<FootholdGraph>());
+        assert!(app.world.contains_resource::<BackGroundEdge>());
+
+        app.world.send_event(ChangeMap {
+            path: "Map/Map/Map0/1000010000.json".to_string(),
+            spawn: SpawnPoint::Portal("sp".to_string()),
+        });
+        app.update();
+
+        assert_eq!(count::<With<MapEntity>>(&mut app), 0);
+        assert_eq!(count::<With<Sprite>>(&mut app), 0);
+        assert_eq!(count::<With<Animations>>(&mut app), 0);
+        assert_eq!(count::<With<BackGround>>(&mut app), 0);
+        assert_eq!(count::<With<BackTiles>>(&mut app), 0);
+        assert_eq!(count::<With<BackEnity>>(&mut app), 0);
+        assert_eq!(count::<With<Collider>>(&mut app), 0);
+        assert!(!app.world.contains_resource::<FootholdGraph>());
+        assert!(!app.world.contains_resource::<BackGroundEdge>());
+        assert!(app.world.get_entity(player).is_some());
+        assert_eq!(
+            app.world.resource::<PlayerSpawn>().0,
+            SpawnPoint::Portal("sp".to_string())
+        );
+        app.update();
+        assert_eq!(
+            *app.world.resource::<State<MapState>>().get(),
+            MapState::Loading
+        );
+    }
+}
@@ -0,0 +1,718 @@
+use std::cmp::{max, min};
+
+use bevy::{
+    asset::{AssetLoader, LoadContext, LoadedAsset},
+    prelude::*,
+    reflect::{TypePath, TypeUuid},
+    utils::BoxedFuture,
+};
+use bevy_rapier2d::prelude::*;
+use serde::Deserialize;
+
+use crate::{
+    foothold::{FootHold, FootHoldType, FootholdGraph},
+    input::Action,
+    map::MapState,
+    player::{Player, PlayerState, PlayerStateChanged, PlayerStateMachine, PLAYER_HALF_HEIGHT},
+    utils::arg_or_env,
+};
+
+//FixedUpdate的时间步长,人物运动和rapier都按这个步长更新
+pub const FIXED_TIMESTEP: f32 = 1.0 / 60.0;
+
+//未通过命令行 --physics 指定时读取的环境变量
+const PHYSICS_ENV: &str = "STUDYMS_PHYSICS";
+
+//人物手感参数,相对于assets目录,修改后自动重新读取
+const PHYSICS_CONFIG: &str = "player.physics.json";
+
+//人物物理后端,rapier为默认,foothold沿地砖解析计算,结果与帧率无关
+#[derive(Debug, Resource, Clone, Copy, Default, PartialEq, Eq)]
+pub enum PhysicsBackend {
+    #[default]
+    Rapier,
+    Foothold,
+}
+
+impl PhysicsBackend {
+    pub fn from_env() -> PhysicsBackend {
+        match arg_or_env("physics", PHYSICS_ENV).as_deref() {
+            None | Some("rapier") => PhysicsBackend::Rapier,
+            Some("foothold") => PhysicsBackend::Foothold,
+            Some(other) => {
+                warn!("unknown physics backend {:?}, fall back to rapier", other);
+                PhysicsBackend::Rapier
+            }
+        }
+    }
+}
+
+//人物运动参数,速度单位为像素/秒
+#[derive(Debug, Resource, Clone, Deserialize, TypeUuid, TypePath)]
+#[uuid = "8c1d3a52-6f0e-4b7a-9e21-5d4c7b9a0f36"]
+#[serde(default)]
+pub struct PhysicsConfig {
+    pub walk_speed: f32,
+    pub jump_force: f32,
+    pub down_jump_force: f32,
+    pub gravity: f32,
+    //最大下落速度
+    pub max_fall_speed: f32,
+    //在斜坡上行走时速度减少的比例
+    pub slope_friction: f32,
+    //下跳后穿过地砖的时间,秒
+    pub down_jump_time: f32,
+    pub climb_speed: f32,
+}
+
+impl Default for PhysicsConfig {
+    fn default() -> Self {
+        PhysicsConfig {
+            walk_speed: 300.0,
+            jump_force: 456.0,
+            down_jump_force: 144.0,
+            gravity: 1200.0,
+            max_fall_speed: 480.0,
+            slope_friction: 0.0,
+            down_jump_time: 0.4,
+            climb_speed: 100.0,
+        }
+    }
+}
+
+impl PhysicsConfig {
+    //在地砖上的水平速度,walk为-1(左),0,1(右)
+    pub fn ground_speed(&self, walk: f32, slope: bool) -> f32 {
+        if slope {
+            walk * self.walk_speed * (1.0 - self.slope_friction).max(0.0)
+        } else {
+            walk * self.walk_speed
+        }
+    }
+
+    //按重力积分dt秒并返回位移,下落速度达到上限后匀速,结果与时间步长无关
+    pub fn integrate(&self, velocity: &mut Vec2, dt: f32) -> Vec2 {
+        let vy = velocity.y;
+        let terminal = -self.max_fall_speed;
+        //加速到最大下落速度所需的时间
+        let t = if self.gravity > 0.0 {
+            ((vy - terminal) / self.gravity).clamp(0.0, dt)
+        } else {
+            dt
+        };
+        let dy = vy * t - 0.5 * self.gravity * t * t + terminal * (dt - t);
+        velocity.y = (vy - self.gravity * dt).max(terminal);
+        Vec2::new(velocity.x * dt, dy)
+    }
+}
+
+#[derive(Debug, Resource)]
+pub struct PhysicsConfigHandle(pub Handle<PhysicsConfig>);
+
+#[derive(Default)]
+pub struct PhysicsConfigLoader;
+
+impl AssetLoader for PhysicsConfigLoader {
+    fn load<'a>(
+        &'a self,
+        bytes: &'a [u8],
+        load_context: &'a mut LoadContext,
+    ) -> BoxedFuture<'a, Result<(), bevy::asset::Error>> {
+        Box::pin(async move {
+            let config: PhysicsConfig = serde_json::from_slice(bytes)?;
+            load_context.set_default_asset(LoadedAsset::new(config));
+            Ok(())
+        })
+    }
+
+    fn extensions(&self) -> &[&str] {
+        &["physics.json"]
+    }
+}
+
+//一次FixedUpdate中的输入,walk为-1(左),0,1(右)
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct MoveInput {
+    pub walk: f32,
+    pub jump: bool,
+    pub down: bool,
+}
+
+//按地砖计算的人物运动
+#[derive(Debug, Component, Clone, Default)]
+pub struct FootholdBody {
+    //人物脚下的位置
+    pub position: Vec2,
+    pub velocity: Vec2,
+    //所在地砖,在空中时为None
+    pub foothold: Option<i32>,
+    //下跳时穿过的地砖
+    pub ignore: Option<i32>,
+    pub input: MoveInput,
+    //上一次写入Transform的位置,不一致时说明人物被传送或攀爬
+    synced: Vec2,
+}
+
+impl FootholdBody {
+    pub fn new(position: Vec2) -> FootholdBody {
+        FootholdBody {
+            position,
+            synced: position,
+            ..default()
+        }
+    }
+
+    //推进dt秒,只依赖地砖数据和输入
+    pub fn step(&mut self, graph: &FootholdGraph, config: &PhysicsConfig, dt: f32) {
+        match self.foothold.and_then(|id| graph.get(id)) {
+            Some(foothold) => self.step_ground(graph, config, foothold.clone(), dt),
+            None => {
+                self.foothold = None;
+                self.step_air(graph, config, dt);
+            }
+        }
+    }
+
+    fn step_ground(
+        &mut self,
+        graph: &FootholdGraph,
+        config: &PhysicsConfig,
+        mut current: FootHold,
+        dt: f32,
+    ) {
+        if self.input.jump {
+            if self.input.down {
+                //下跳,下面有地砖时穿过当前地砖
+                if graph
+                    .find_below(self.position - Vec2::new(0.0, 1.0))
+                    .is_some()
+                {
+                    self.ignore = Some(current.id);
+                    self.foothold = None;
+                    self.velocity = Vec2::new(0.0, config.down_jump_force);
+                }
+                return;
+            }
+            self.foothold = None;
+            self.velocity = Vec2::new(self.input.walk * config.walk_speed, config.jump_force);
+            self.step_air(graph, config, dt);
+            return;
+        }
+
+        let slope = current.y1 != current.y2;
+        self.velocity = Vec2::new(config.ground_speed(self.input.walk, slope), 0.0);
+        let mut x =
+            (self.position.x + self.velocity.x * dt).clamp(graph.left as f32, graph.right as f32);
+        loop {
+            let left = min(current.x1, current.x2) as f32;
+            let right = max(current.x1, current.x2) as f32;
+            let (edge, next) = if x > right {
+                (right, graph.right_of(current.id))
+            } else if x < left {
+                (left, graph.left_of(current.id))
+            } else {
+                break;
+            };
+            let edge_y = current.y_at(edge).unwrap_or(self.position.y);
+            match next {
+                //往上的墙挡住人物
+                Some(wall) if wall.is_wall() && -(min(wall.y1, wall.y2) as f32) > edge_y => {
+                    x = edge;
+                    break;
+                }
+                Some(next) if !next.is_wall() => current = next.clone(),
+                //走出链的末端或往下的墙,开始下落
+                _ => {
+                    self.foothold = None;
+                    self.position = Vec2::new(x, edge_y);
+                    return;
+                }
+            }
+        }
+        self.position = Vec2::new(x, current.y_at(x).unwrap_or(self.position.y));
+        self.foothold = Some(current.id);
+    }
+
+    fn step_air(&mut self, graph: &FootholdGraph, config: &PhysicsConfig, dt: f32) {
+        let from = self.position;
+        let mut to = from + config.integrate(&mut self.velocity, dt);
+        to.x = to.x.clamp(graph.left as f32, graph.right as f32);
+
+        //在空中只与落点layer的墙碰撞
+        let layer = graph.find_below(from).map(|foothold| foothold.layer);
+        for wall in graph.footholds.values() {
+            if !wall.is_wall() || Some(wall.layer) != layer || graph.is_invalid_wall(wall.id) {
+                continue;
+            }
+            let x = wall.x1 as f32;
+            let top = -(min(wall.y1, wall.y2) as f32);
+            let bottom = -(max(wall.y1, wall.y2) as f32);
+            //从上往下画的墙挡住向左的移动,从下往上画的墙挡住向右的移动
+            let blocks = if wall.y1 < wall.y2 {
+                from.x >= x && to.x < x
+            } else {
+                from.x <= x && to.x > x
+            };
+            if blocks && to.y <= top && to.y >= bottom {
+                to.x = x;
+                self.velocity.x = 0.0;
+            }
+        }
+
+        if self.velocity.y <= 0.0 {
+            //落在穿过的最高的地砖上
+            let mut landing: Option<(i32, f32)> = None;
+            for foothold in graph.footholds.values() {
+                if foothold.is_wall() || foothold.is_ceiling() || Some(foothold.id) == self.ignore {
+                    continue;
+                }
+                let Some(to_y) = foothold.y_at(to.x) else {
+                    continue;
+                };
+                let from_y = foothold.y_at(from.x).unwrap_or(to_y);
+                if from.y >= from_y && to.y <= to_y && !matches!(landing, Some((_, y)) if y >= to_y)
+                {
+                    landing = Some((foothold.id, to_y));
+                }
+            }
+            if let Some((id, y)) = landing {
+                to.y = y;
+                self.velocity.y = 0.0;
+                self.foothold = Some(id);
+                self.ignore = None;
+            }
+        }
+        self.position = to;
+    }
+}
+
+pub struct PhysicsPlugin;
+
+impl Plugin for PhysicsPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_asset::<PhysicsConfig>()
+            .init_asset_loader::<PhysicsConfigLoader>()
+            .init_resource::<PhysicsConfig>()
+            .insert_resource(PhysicsBackend::from_env())
+            .insert_resource(FixedTime::new_from_secs(FIXED_TIMESTEP))
+            .insert_resource(RapierConfiguration {
+                timestep_mode: TimestepMode::Fixed {
+                    dt: FIXED_TIMESTEP,
+                    substeps: 1,
+                },
+                ..default()
+            })
+            //rapier在FixedUpdate中运行,角色控制器每一步都会被执行
+            .configure_sets(
+                FixedUpdate,
+                (
+                    PhysicsSet::SyncBackend,
+                    PhysicsSet::SyncBackendFlush,
+                    PhysicsSet::StepSimulation,
+                    PhysicsSet::Writeback,
+                )
+                    .chain(),
+            )
+            .add_systems(
+                FixedUpdate,
+                (
+                    RapierPhysicsPlugin::<NoUserData>::get_systems(PhysicsSet::SyncBackend)
+                        .in_set(PhysicsSet::SyncBackend),
+                    RapierPhysicsPlugin::<NoUserData>::get_systems(PhysicsSet::SyncBackendFlush)
+                        .in_set(PhysicsSet::SyncBackendFlush),
+                    RapierPhysicsPlugin::<NoUserData>::get_systems(PhysicsSet::StepSimulation)
+                        .in_set(PhysicsSet::StepSimulation),
+                    RapierPhysicsPlugin::<NoUserData>::get_systems(PhysicsSet::Writeback)
+                        .in_set(PhysicsSet::Writeback),
+                ),
+            )
+            .add_systems(Startup, setup_physics_config)
+            .add_systems(Update, update_physics_config)
+            .add_systems(
+                Update,
+                add_foothold_body.run_if(resource_equals(PhysicsBackend::Foothold)),
+            )
+            .add_systems(
+                FixedUpdate,
+                (update_foothold_body, update_foothold_state)
+                    .chain()
+                    .run_if(
+                        resource_equals(PhysicsBackend::Foothold)
+                            .and_then(in_state(MapState::Loaded)),
+                    ),
+            )
+            .add_systems(
+                Update,
+                update_foothold_input.run_if(
+                    resource_equals(PhysicsBackend::Foothold).and_then(in_state(MapState::Loaded)),
+                ),
+            );
+    }
+}
+
+fn setup_physics_config(mut commands: Commands, asset_server: Res<AssetServer>) {
+    let handle = asset_server.load(PHYSICS_CONFIG);
+    commands.insert_resource(PhysicsConfigHandle(handle));
+}
+
+//配置文件读取或修改后替换当前参数,读取失败时保留默认值
+fn update_physics_config(
+    mut events: EventReader<AssetEvent<PhysicsConfig>>,
+    handle: Option<Res<PhysicsConfigHandle>>,
+    configs: Res<Assets<PhysicsConfig>>,
+    mut config: ResMut<PhysicsConfig>,
+) {
+    let Some(handle) = handle else {
+        return;
+    };
+    for event in events.iter() {
+        match event {
+            AssetEvent::Created { handle: changed } | AssetEvent::Modified { handle: changed }
+                if *changed == handle.0 =>
+            {
+                if let Some(loaded) = configs.get(changed) {
+                    *config = loaded.clone();
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+fn add_foothold_body(mut commands: Commands, query: Query<(Entity, &Transform), Added<Player>>) {
+    for (entity, transform) in query.iter() {
+        let feet = Vec2::new(
+            transform.translation.x,
+            transform.translation.y - PLAYER_HALF_HEIGHT,
+        );
+        commands.entity(entity).insert(FootholdBody::new(feet));
+    }
+}
+
+fn update_foothold_input(
+    input: Res<Input<Action>>,
+    mut query: Query<(&mut FootholdBody, &PlayerStateMachine)>,
+) {
+    for (mut body, state) in query.iter_mut() {
+        if state.current() == PlayerState::Climb {
+            continue;
+        }
+        let mut walk = 0.0;
+        if input.pressed(Action::MoveRight) {
+            walk = 1.0;
+        } else if input.pressed(Action::MoveLeft) {
+            walk = -1.0;
+        }
+        body.input = MoveInput {
+            walk,
+            jump: input.pressed(Action::Jump),
+            down: input.pressed(Action::Crouch),
+        };
+    }
+}
+
+//固定时间步长推进,并把结果写回Transform
+fn update_foothold_body(
+    time: Res<FixedTime>,
+    config: Res<PhysicsConfig>,
+    graph: Option<Res<FootholdGraph>>,
+    mut query: Query<(
+        &mut FootholdBody,
+        &mut Transform,
+        &mut Player,
+        &PlayerStateMachine,
+    )>,
+) {
+    let Some(graph) = graph else {
+        return;
+    };
+    let dt = time.period.as_secs_f32();
+    for (mut body, mut transform, mut player, state) in query.iter_mut() {
+        if state.current() == PlayerState::Climb {
+            continue;
+        }
+        let feet = Vec2::new(
+            transform.translation.x,
+            transform.translation.y - PLAYER_HALF_HEIGHT,
+        );
+        //人物被传送或刚离开梯子,从当前位置开始下落
+        if feet.distance(body.synced) > 0.01 {
+            body.position = feet;
+            body.velocity = player.velocity;
+            body.foothold = None;
+        }
+        body.step(&graph, &config, dt);
+        body.synced = body.position;
+        player.velocity = body.velocity;
+        transform.translation.x = body.position.x;
+        transform.translation.y = body.position.y + PLAYER_HALF_HEIGHT;
+    }
+}
+
+//根据地砖更新人物状态和所在layer
+fn update_foothold_state(
+    graph: Option<Res<FootholdGraph>>,
+    mut query: Query<(&FootholdBody, &mut Player, &mut PlayerStateMachine)>,
+    mut state_ev: EventWriter<PlayerStateChanged>,
+) {
+    let Some(graph) = graph else {
+        return;
+    };
+    for (body, mut player, mut state) in query.iter_mut() {
+        if state.current() == PlayerState::Climb {
+            continue;
+        }
+        let next = match body.foothold.and_then(|id| graph.get(id)) {
+            Some(foothold) => {
+                player.layer = foothold.layer;
+                player.foot_hold_type = FootHold::get_foothold_type(
+                    Vec2::new(foothold.x1 as f32, -foothold.y1 as f32),
+                    Vec2::new(foothold.x2 as f32, -foothold.y2 as f32),
+                );
+                if body.velocity.x != 0.0 {
+                    PlayerState::Walk
+                } else if body.input.down {
+                    PlayerState::Prone
+                } else {
+                    PlayerState::Stand
+                }
+            }
+            None => {
+                player.layer = -1;
+                player.foot_hold_type = FootHoldType::Unknow;
+                if body.ignore.is_some() {
+                    PlayerState::DownJump
+                } else if body.velocity.y > 0.0 {
+                    PlayerState::Jump
+                } else {
+                    PlayerState::Fall
+                }
+            }
+        };
+        state_ev.send_batch(state.set(next));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const DT: f32 = FIXED_TIMESTEP;
+
+    //坐标和json一样y轴向下
+    fn foothold(
+        id: i32,
+        (x1, y1): (i32, i32),
+        (x2, y2): (i32, i32),
+        prev: i32,
+        next: i32,
+    ) -> FootHold {
+        FootHold {
+            x1,
+            y1,
+            x2,
+            y2,
+            prev,
+            next,
+            piece: 0,
+            layer: 0,
+            id,
+        }
+    }
+
+    //以dt为步长从地面起跳1秒,返回最高点和每隔1/6秒的高度
+    fn simulate_jump(config: &PhysicsConfig, dt: f32) -> (f32, Vec<f32>) {
+        let steps_per_sample = (1.0 / 6.0 / dt).round() as usize;
+        let mut velocity = Vec2::new(0.0, config.jump_force);
+        let mut y = 0.0;
+        let mut apex: f32 = 0.0;
+        let mut samples = Vec::new();
+        for step in 1..=steps_per_sample * 6 {
+            y += config.integrate(&mut velocity, dt).y;
+            apex = apex.max(y);
+            if step % steps_per_sample == 0 {
+                samples.push(y);
+            }
+        }
+        (apex, samples)
+    }
+
+    //解析解,达到最大下落速度后匀速
+    fn jump_height(config: &PhysicsConfig, t: f32) -> f32 {
+        let v = config.jump_force;
+        let g = config.gravity;
+        let t0 = (v + config.max_fall_speed) / g;
+        if t <= t0 {
+            v * t - 0.5 * g * t * t
+        } else {
+            v * t0 - 0.5 * g * t0 * t0 - config.max_fall_speed * (t - t0)
+        }
+    }
+
+    #[test]
+    fn jump_is_independent_of_timestep() {
+        let config = PhysicsConfig::default();
+        let expected_apex = config.jump_force * config.jump_force / (2.0 * config.gravity);
+        for dt in [1.0 / 30.0, 1.0 / 60.0, 1.0 / 144.0] {
+            let (apex, samples) = simulate_jump(&config, dt);
+            //最高点不一定正好落在步长上,1/30秒时最多差g*(dt/2)^2/2 ≈ 0.17像素
+            assert!(
+                (apex - expected_apex).abs() < 0.5,
+                "dt {}: apex {} != {}",
+                dt,
+                apex,
+                expected_apex
+            );
+            //1秒时已经达到最大下落速度
+            for (i, y) in samples.iter().enumerate() {
+                let t = (i + 1) as f32 / 6.0;
+                let expected = jump_height(&config, t);
+                assert!(
+                    (y - expected).abs() < 0.05,
+                    "dt {}: y({}) = {} != {}",
+                    dt,
+                    t,
+                    y,
+                    expected
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn fall_speed_is_clamped() {
+        let config = PhysicsConfig::default();
+        let mut velocity = Vec2::ZERO;
+        let dy = config.integrate(&mut velocity, 2.0).y;
+        assert_eq!(velocity.y, -config.max_fall_speed);
+        //0.4秒加速到480后匀速1.6秒
+        let t0 = config.max_fall_speed / config.gravity;
+        let expected = -0.5 * config.gravity * t0 * t0 - config.max_fall_speed * (2.0 - t0);
+        assert!((dy - expected).abs() < 1e-3, "{} != {}", dy, expected);
+    }
+
+    //没有输入时一直推进,直到落地或超时
+    fn step_until_landed(body: &mut FootholdBody, graph: &FootholdGraph, config: &PhysicsConfig) {
+        body.input = MoveInput::default();
+        for _ in 0..600 {
+            body.step(graph, config, DT);
+            if body.foothold.is_some() {
+                return;
+            }
+        }
+        panic!("body never landed: {:?}", body);
+    }
+
+    #[test]
+    fn walks_across_next_prev_handoff() {
+        let graph = FootholdGraph::new(&[
+            foothold(1, (0, 0), (100, 0), 0, 2),
+            foothold(2, (100, 0), (200, 0), 1, 0),
+        ]);
+        let config = PhysicsConfig::default();
+        let mut body = FootholdBody::new(Vec2::new(90.0, 0.0));
+        body.foothold = Some(1);
+        body.input.walk = 1.0;
+        body.step(&graph, &config, 0.1);
+        assert_eq!(body.foothold, Some(2));
+        assert_eq!(body.position, Vec2::new(120.0, 0.0));
+
+        //往回走交还给prev
+        body.input.walk = -1.0;
+        body.step(&graph, &config, 0.1);
+        assert_eq!(body.foothold, Some(1));
+        assert_eq!(body.position, Vec2::new(90.0, 0.0));
+    }
+
+    #[test]
+    fn falls_off_the_end_of_a_chain() {
+        let graph = FootholdGraph::new(&[
+            foothold(1, (0, 0), (100, 0), 0, 2),
+            foothold(2, (100, 0), (200, 0), 1, 0),
+            foothold(3, (0, 100), (400, 100), 0, 0),
+        ]);
+        let config = PhysicsConfig::default();
+        let mut body = FootholdBody::new(Vec2::new(190.0, 0.0));
+        body.foothold = Some(2);
+        body.input.walk = 1.0;
+        body.step(&graph, &config, 0.1);
+        assert_eq!(body.foothold, None);
+        assert_eq!(body.position, Vec2::new(220.0, 0.0));
+
+        step_until_landed(&mut body, &graph, &config);
+        assert_eq!(body.foothold, Some(3));
+        assert_eq!(body.position.y, -100.0);
+    }
+
+    #[test]
+    fn upward_wall_blocks_walking() {
+        let graph = FootholdGraph::new(&[
+            foothold(1, (0, 0), (100, 0), 0, 2),
+            foothold(2, (100, 0), (100, -50), 1, 3),
+            foothold(3, (100, -50), (200, -50), 2, 0),
+        ]);
+        let config = PhysicsConfig::default();
+        let mut body = FootholdBody::new(Vec2::new(90.0, 0.0));
+        body.foothold = Some(1);
+        body.input.walk = 1.0;
+        for _ in 0..10 {
+            body.step(&graph, &config, DT);
+        }
+        assert_eq!(body.foothold, Some(1));
+        assert_eq!(body.position, Vec2::new(100.0, 0.0));
+    }
+
+    #[test]
+    fn lands_on_slope() {
+        //从(0,-100)升到(200,0)的斜坡
+        let graph = FootholdGraph::new(&[foothold(1, (0, 100), (200, 0), 0, 0)]);
+        let config = PhysicsConfig::default();
+        let mut body = FootholdBody::new(Vec2::new(100.0, 50.0));
+        step_until_landed(&mut body, &graph, &config);
+        assert_eq!(body.foothold, Some(1));
+        assert_eq!(body.position, Vec2::new(100.0, -50.0));
+        assert_eq!(body.velocity.y, 0.0);
+    }
+
+    #[test]
+    fn down_jump_ignores_current_foothold() {
+        let graph = FootholdGraph::new(&[
+            foothold(1, (0, 0), (200, 0), 0, 0),
+            foothold(2, (0, 100), (200, 100), 0, 0),
+        ]);
+        let config = PhysicsConfig::default();
+        let mut body = FootholdBody::new(Vec2::new(100.0, 0.0));
+        body.foothold = Some(1);
+        body.input = MoveInput {
+            walk: 0.0,
+            jump: true,
+            down: true,
+        };
+        body.step(&graph, &config, DT);
+        assert_eq!(body.foothold, None);
+        assert_eq!(body.ignore, Some(1));
+        assert_eq!(body.velocity.y, config.down_jump_force);
+
+        //跳起后落下穿过地砖1,落在地砖2上
+        step_until_landed(&mut body, &graph, &config);
+        assert_eq!(body.foothold, Some(2));
+        assert_eq!(body.position.y, -100.0);
+        assert_eq!(body.ignore, None);
+    }
+
+    #[test]
+    fn down_jump_without_floor_below_does_nothing() {
+        let graph = FootholdGraph::new(&[foothold(1, (0, 0), (200, 0), 0, 0)]);
+        let config = PhysicsConfig::default();
+        let mut body = FootholdBody::new(Vec2::new(100.0, 0.0));
+        body.foothold = Some(1);
+        body.input = MoveInput {
+            walk: 0.0,
+            jump: true,
+            down: true,
+        };
+        body.step(&graph, &config, DT);
+        assert_eq!(body.foothold, Some(1));
+        assert_eq!(body.ignore, None);
+    }
+}
@@ -1,30 +1,73 @@
 use crate::{
-    animate::{Animation, AnimationIndices, AnimationTimer},
-    foothold::{self, FootHold, FootHoldType},
-    utils::composite_zindex,
+    animate::{Animation, AnimationSet},
+    foothold::{self, FootHold, FootHoldType, FootholdGraph, FLOOR_GROUP},
+    input::Action,
+    ladder::LadderRope,
+    map::{map_path, ChangeMap, MapData, MapHandle, MapState, PlayerSpawn, SpawnPoint},
+    physics::{PhysicsBackend, PhysicsConfig},
+    utils::{cal_ax, cal_ay, composite_zindex},
 };
-use bevy::{app::RunFixedUpdateLoop, asset::LoadState, prelude::*, utils::HashMap};
+use bevy::{asset::LoadState, prelude::*, utils::HashMap};
 use bevy_rapier2d::{na::ComplexField, prelude::*};
+use std::path::Path;
 
 // 人物状态切换
-#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Event)]
-pub struct StateChangeEvent;
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Event)]
+pub struct PlayerStateChanged {
+    pub from: PlayerState,
+    pub to: PlayerState,
+}
 
 #[derive(Debug, Component, Clone, Default)]
 pub struct Player {
-    pub translation: Vect,
+    //速度,像素/秒
+    pub velocity: Vect,
     pub layer: i32,
     pub foot_hold_type: FootHoldType,
 }
 
+//人物动作定义文件,相对于assets目录
+pub const PLAYER_ANIMATION: &str = "player.animation.json";
+
 #[derive(Debug, Resource)]
 pub struct PlayerAssets {
+    pub definition: Handle<AnimationSet>,
+    //动作定义读取后才加载图片
     pub handle_map: HashMap<String, Vec<Handle<Image>>>,
 }
 
 #[derive(Debug, Resource)]
 pub struct AnimateAssets {
     pub animate_map: HashMap<String, Animation>,
+    //缺少动作时使用的动作
+    pub fallback: String,
+}
+
+impl AnimateAssets {
+    //缺少动作时用jump代替,没有jump时用按名字排序的第一个动作,一个动作都没有时返回None
+    pub fn new(animate_map: HashMap<String, Animation>) -> Option<AnimateAssets> {
+        let fallback = if animate_map.contains_key("jump") {
+            "jump".to_string()
+        } else {
+            let first = animate_map.keys().min()?.clone();
+            error!(
+                "{} has no jump action, use {} instead",
+                PLAYER_ANIMATION, first
+            );
+            first
+        };
+        Some(AnimateAssets {
+            animate_map,
+            fallback,
+        })
+    }
+
+    pub fn get(&self, name: &str) -> &Animation {
+        self.animate_map.get(name).unwrap_or_else(|| {
+            debug!("player action {} not found, use {}", name, self.fallback);
+            &self.animate_map[&self.fallback]
+        })
+    }
 }
 
 // 脸朝向
@@ -35,18 +78,104 @@ pub enum Direction {
     Right,
 }
 
-#[derive(Debug, Resource, Clone, Copy, Default, PartialEq, Eq, Reflect, Component)]
-#[reflect(Resource)]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash, Reflect)]
 pub enum PlayerState {
     #[default]
-    Standing,
-    Walking,
-    Jumping,
+    Stand,
+    Walk,
+    Jump,
+    Fall,
     Prone,
+    //下跳,穿过脚下的地砖
+    DownJump,
+    Climb,
+    Hit,
+    Dead,
+}
+
+impl PlayerState {
+    pub fn on_ground(self) -> bool {
+        matches!(
+            self,
+            PlayerState::Stand | PlayerState::Walk | PlayerState::Prone
+        )
+    }
+
+    //允许的状态切换
+    pub fn can_change(self, to: PlayerState) -> bool {
+        use PlayerState::*;
+        if self == to {
+            return false;
+        }
+        match (self, to) {
+            //死亡后只能在出生点复活
+            (Dead, to) => to == Fall,
+            (_, Hit | Dead) => true,
+            (Stand | Walk | Prone, _) => true,
+            //在空中可以落地或抓住梯子
+            (Jump | DownJump, Fall) => true,
+            (Jump | Fall | DownJump, to) => to.on_ground() || to == Climb,
+            (Climb, Jump | Fall) => true,
+            (Hit, _) => true,
+            _ => false,
+        }
+    }
+
+    //状态对应的人物动作,使用wz中的动作名,整张图片和角色部件共用
+    //默认的player.animation.json没有ladder和rope,由AnimateAssets用jump代替
+    pub fn animation(self, climb: Option<&Climb>) -> &'static str {
+        match self {
+            PlayerState::Stand => "stand1",
+            PlayerState::Walk => "walk1",
+            PlayerState::Prone => "prone",
+            PlayerState::Jump | PlayerState::Fall | PlayerState::DownJump => "jump",
+            PlayerState::Climb if climb.is_some_and(|climb| !climb.0.is_ladder()) => "rope",
+            PlayerState::Climb => "ladder",
+            PlayerState::Hit => "alert",
+            PlayerState::Dead => "dead",
+        }
+    }
+}
+
+//人物状态机,所有状态切换都通过set进行
+#[derive(Debug, Component, Clone, Default)]
+pub struct PlayerStateMachine {
+    current: PlayerState,
+    //进入当前状态后经过的时间,秒
+    elapsed: f32,
 }
 
-#[derive(Component, Clone, Default, Debug)]
-pub struct DownJumpTimer(pub Timer);
+impl PlayerStateMachine {
+    pub fn new(state: PlayerState) -> PlayerStateMachine {
+        PlayerStateMachine {
+            current: state,
+            elapsed: 0.0,
+        }
+    }
+
+    pub fn current(&self) -> PlayerState {
+        self.current
+    }
+
+    pub fn elapsed(&self) -> f32 {
+        self.elapsed
+    }
+
+    pub fn tick(&mut self, dt: f32) {
+        self.elapsed += dt;
+    }
+
+    //切换到新状态,不允许的切换返回None
+    pub fn set(&mut self, to: PlayerState) -> Option<PlayerStateChanged> {
+        if !self.current.can_change(to) {
+            return None;
+        }
+        let from = self.current;
+        self.current = to;
+        self.elapsed = 0.0;
+        Some(PlayerStateChanged { from, to })
+    }
+}
 
 #[derive(Clone, Default, Bundle)]
 pub struct PlayerBundle {
@@ -70,32 +199,28 @@ enum Load {
     Loading,
     AssetsLoaded,
     PlayerFinished,
+    //动作定义读取失败,不生成人物
+    Failed,
 }
 
 #[derive(Component)]
 struct Jump(f32, f32);
 
-const PLAYER_VELOCITY_X: f32 = 300.0;
-const GRAVITY: f32 = 10.0;
-// const GRAVITY: f32 = 0.0;
 //人在地砖上对地砖的力
 const MIN_FORCE: f32 = 1.0e-3;
-// const GROUND_FORCE: f32 = 0.0;
-
-const MAX_JUMP_HEIGHT: f32 = 7.6;
+//在斜坡上把人物压向地砖的位移
+const SLOPE_FORCE: f32 = 10.0;
 
-const MAX_FALL_SPEED: f32 = 8.0;
-
-#[derive(Debug, Component, Clone, Default)]
-pub struct Ground;
-#[derive(Debug, Component, Clone, Default)]
-pub struct Rise;
-#[derive(Debug, Component, Clone, Default)]
-pub struct Fall;
+//人物碰撞体半高,人物脚下的位置为translation.y - PLAYER_HALF_HEIGHT
+pub const PLAYER_HALF_HEIGHT: f32 = 32.0;
 
 #[derive(Debug, Component, Clone, Default)]
 pub struct CurrentFootHold;
 
+//正在攀爬的梯子或绳子,只在Climb状态存在
+#[derive(Debug, Component, Clone)]
+pub struct Climb(pub LadderRope);
+
 pub struct PlayerPlugin;
 
 impl Plugin for PlayerPlugin {
@@ -103,52 +228,101 @@ impl Plugin for PlayerPlugin {
         app.add_state::<Load>()
             .add_systems(OnEnter(Load::Setup), setup_player_assets)
             .add_systems(OnEnter(Load::AssetsLoaded), player) //生成人物
+            .add_systems(OnEnter(MapState::Loaded), respawn_player) //切换地图后回到出生点
+            //地图通常比人物先读取完成,人物生成后也要放到出生点
+            .add_systems(
+                OnEnter(Load::PlayerFinished),
+                respawn_player.run_if(in_state(MapState::Loaded)),
+            )
             .add_systems(
                 Update,
                 check_textures.run_if(in_state(Load::Loading)), //等待人物读取完成
             )
             .add_systems(
                 PreUpdate,
-                (update_ground, update_layer, update_foothold)
-                    .run_if(in_state(Load::PlayerFinished)),
+                update_layer
+                    .run_if(in_state(Load::PlayerFinished).and_then(in_state(MapState::Loaded))),
             )
             .add_systems(
-                RunFixedUpdateLoop,
-                update_player_animation.run_if(in_state(Load::PlayerFinished)),
-            )
-            .add_systems(
-                Update,
+                FixedUpdate,
                 (
-                    update_flip,
-                    update_group,
+                    update_state_time,
+                    update_ground,
+                    update_foothold,
                     update_edge,
-                    update_fall,
+                    update_collision,
+                    update_group,
                     update_downjump,
                     update_input,
                     update_rise,
-                    update_collision,
+                    update_fall,
+                )
+                    .chain()
+                    .before(PhysicsSet::SyncBackend)
+                    //foothold后端不使用rapier的角色控制器
+                    .run_if(
+                        in_state(Load::PlayerFinished)
+                            .and_then(in_state(MapState::Loaded))
+                            .and_then(resource_equals(PhysicsBackend::Rapier)),
+                    ),
+            )
+            .add_systems(
+                Update,
+                (
                     update_direction,
+                    update_flip,
+                    update_portal,
+                    update_climb_start,
+                    update_climb,
+                    update_player_animation,
                 )
-                    .run_if(in_state(Load::PlayerFinished)), //先读取人物动画,否则会导致读取失败
+                    .chain()
+                    //先读取人物动画和地图,否则会导致读取失败
+                    .run_if(in_state(Load::PlayerFinished).and_then(in_state(MapState::Loaded))),
             )
-            .add_event::<StateChangeEvent>();
+            .add_event::<PlayerStateChanged>();
     }
 }
 
-//等待人物动作加载完成
+//等待人物动作加载完成,缺少的图片(例如ladder,rope)加载失败时跳过
 fn check_textures(
     mut next_state: ResMut<NextState<Load>>,
-    assets: ResMut<PlayerAssets>,
-    image: ResMut<Assets<Image>>,
+    mut assets: ResMut<PlayerAssets>,
     asset_server: Res<AssetServer>,
+    sets: Res<Assets<AnimationSet>>,
 ) {
+    //先读取动作定义,再加载定义中的图片
+    if assets.handle_map.is_empty() {
+        let Some(set) = sets.get(&assets.definition) else {
+            if asset_server.get_load_state(&assets.definition) == LoadState::Failed {
+                error!("failed to load {}", PLAYER_ANIMATION);
+                next_state.set(Load::Failed);
+            }
+            return;
+        };
+        assets.handle_map = set
+            .actions
+            .iter()
+            .map(|(name, action)| {
+                let images = action
+                    .frames
+                    .iter()
+                    .map(|frame| asset_server.load(&frame.image))
+                    .collect();
+                (name.clone(), images)
+            })
+            .collect();
+        return;
+    }
     // Advance the `AppState` once all sprite handles have been loaded by the `AssetServer`
-    for map in &assets.handle_map {
-        if LoadState::Loaded == asset_server.get_group_load_state(map.1.iter().map(|h| h.id())) {
-            next_state.set(Load::AssetsLoaded);
-        } else {
-            next_state.set(Load::Loading);
-        }
+    let finished = assets.handle_map.values().flatten().all(|handle| {
+        matches!(
+            asset_server.get_load_state(handle),
+            LoadState::Loaded | LoadState::Failed
+        )
+    });
+    if finished {
+        next_state.set(Load::AssetsLoaded);
     }
 }
 
@@ -158,8 +332,10 @@ fn player(
     mut texture_atlases: ResMut<Assets<TextureAtlas>>,
     mut textures: ResMut<Assets<Image>>,
     assets: ResMut<PlayerAssets>,
+    sets: Res<Assets<AnimationSet>>,
     mut next_state: ResMut<NextState<Load>>,
 ) {
+    let set = sets.get(&assets.definition).unwrap();
     let mut texture_atlas_builder = TextureAtlasBuilder::default();
     for map in &assets.handle_map {
         for vecs in map.1 {
@@ -173,25 +349,49 @@ fn player(
             texture_atlas_builder.add_texture(vecs.clone(), texture);
         }
     }
-    let texture_atlas = texture_atlas_builder.finish(&mut textures).unwrap();
+    let texture_atlas = match texture_atlas_builder.finish(&mut textures) {
+        Ok(texture_atlas) => texture_atlas,
+        Err(err) => {
+            error!("failed to build player texture atlas: {:?}", err);
+            next_state.set(Load::Failed);
+            return;
+        }
+    };
 
     let mut animate_map = HashMap::new();
 
-    for map in &assets.handle_map {
+    for (name, handles) in &assets.handle_map {
         let mut indices = Vec::new();
-        for handle in map.1 {
-            indices.push(texture_atlas.get_texture_index(&handle).unwrap())
+        let mut delays = Vec::new();
+        let mut anchors = Vec::new();
+        let action = &set.actions[name];
+        //跳过加载失败的帧
+        for (handle, frame) in handles.iter().zip(&action.frames) {
+            if let Some(index) = texture_atlas.get_texture_index(handle) {
+                let size = texture_atlas.textures[index].size();
+                let ox = frame.origin_x.map_or(size.x / 2.0, |x| x as f32);
+                let oy = frame.origin_y.map_or(size.y, |y| y as f32);
+                //原点在脚下,人物的translation在碰撞体中心,锚点再向上移半个碰撞体
+                let anchor = Vec2::new(
+                    cal_ax(ox, size.x),
+                    -cal_ay(oy, size.y) + PLAYER_HALF_HEIGHT / size.y,
+                );
+                indices.push(index);
+                delays.push(frame.delay as f32 / 1000.0);
+                anchors.push(anchor);
+            }
         }
-        let animate = Animation {
-            timer: AnimationTimer(Timer::from_seconds(0.2, TimerMode::Repeating)),
-            indices: AnimationIndices {
-                index: 0,
-                sprite_indices: indices,
-            },
-            name: map.0.to_string(),
-        };
-        animate_map.insert(map.0.to_string(), animate);
+        if indices.is_empty() {
+            continue;
+        }
+        let animate = Animation::new(name, indices, delays, anchors, action.loop_mode);
+        animate_map.insert(name.to_string(), animate);
     }
+    let Some(animate_assets) = AnimateAssets::new(animate_map) else {
+        error!("{} has no action with loaded images", PLAYER_ANIMATION);
+        next_state.set(Load::Failed);
+        return;
+    };
     let texture_atlas_handle = texture_atlases.add(texture_atlas);
 
     commands.spawn((
@@ -199,22 +399,23 @@ fn player(
             sprite_bundle: SpriteSheetBundle {
                 sprite: TextureAtlasSprite {
                     index: 0,
-                    // anchor: bevy::sprite::Anchor::Custom(Vec2::new(0.0, -0.5)),
                     ..default()
                 },
                 texture_atlas: texture_atlas_handle.clone(),
                 transform: Transform::from_xyz(0.0, 0.0, 800.0),
                 ..default()
             },
-            animation: animate_map.get("walk").unwrap().clone(),
+            animation: animate_assets
+                .get(PlayerState::Fall.animation(None))
+                .clone(),
             rigid_body: RigidBody::KinematicPositionBased,
             rotation_constraints: LockedAxes::ROTATION_LOCKED,
-            collider: Collider::cuboid(16.0, 32.0),
+            collider: Collider::cuboid(16.0, PLAYER_HALF_HEIGHT),
             // collider: Collider::capsule_y(18.0, 16.0),
             velocity: Velocity::zero(),
             restitution: Restitution::new(0.0),
             player: Player {
-                translation: Vect::ZERO,
+                velocity: Vect::ZERO,
                 foot_hold_type: FootHoldType::Unknow,
                 layer: 0,
             },
@@ -226,130 +427,301 @@ fn player(
             },
         },
         Ccd::enabled(),
-        Fall,
+        PlayerStateMachine::new(PlayerState::Fall),
     ));
-    commands.insert_resource(AnimateAssets {
-        animate_map: animate_map,
-    });
+    commands.insert_resource(animate_assets);
     next_state.set(Load::PlayerFinished);
 }
 
-fn update_input(
-    input: Res<Input<KeyCode>>,
-    time: Res<Time>,
+//地图加载完成后,把人物放到出生点
+fn respawn_player(
     mut commands: Commands,
-    mut query: Query<
-        (
-            Entity,
-            &mut Player,
-            &mut Animation,
-            &mut KinematicCharacterController,
-        ),
-        With<Ground>,
-    >,
-    assets: ResMut<AnimateAssets>,
-    mut state_change_ev: EventWriter<StateChangeEvent>,
+    spawn: Res<PlayerSpawn>,
+    map: Res<MapHandle>,
+    maps: Res<Assets<MapData>>,
+    mut query: Query<(Entity, &mut Transform, &mut Player, &mut PlayerStateMachine)>,
+    mut state_ev: EventWriter<PlayerStateChanged>,
 ) {
     if query.is_empty() {
         return;
     }
-    // let (mut enity, mut player, mut controller, output) = query.single_mut();
-    let (entity, mut player, mut animation, mut controller) = query.single_mut();
-    player.translation.x = 0.0;
-    player.translation.y = 0.0;
-    if input.pressed(KeyCode::AltLeft) && input.pressed(KeyCode::Down) {
-        player.translation.y = 2.4;
-        commands
-            .entity(entity)
-            .insert(DownJumpTimer(Timer::from_seconds(0.4, TimerMode::Once)));
-        commands.entity(entity).remove::<Ground>();
-        commands.entity(entity).insert(Rise);
-
-        let dt = time.delta_seconds();
-        player.translation.y -= GRAVITY * dt * 2.0;
-        controller.translation = Some(Vec2::new(player.translation.x, player.translation.y));
-    } else if input.pressed(KeyCode::AltLeft) {
-        player.translation.y = MAX_JUMP_HEIGHT;
-        if input.pressed(KeyCode::Right) {
-            player.translation.x = time.delta_seconds() * PLAYER_VELOCITY_X;
-        } else if input.pressed(KeyCode::Left) {
-            player.translation.x = time.delta_seconds() * PLAYER_VELOCITY_X * -1.0;
+    let Some(data) = maps.get(&map.0) else {
+        return;
+    };
+    let position = data.spawn_position(&spawn.0);
+    let (entity, mut transform, mut player, mut state) = query.single_mut();
+    transform.translation.x = position.x;
+    transform.translation.y = position.y + PLAYER_HALF_HEIGHT;
+    player.velocity = Vect::ZERO;
+    player.layer = -1;
+    commands.entity(entity).remove::<Climb>();
+    state_ev.send_batch(state.set(PlayerState::Fall));
+}
+
+//在传送门前按上键进入传送门
+fn update_portal(
+    input: Res<Input<Action>>,
+    asset_server: Res<AssetServer>,
+    map: Res<MapHandle>,
+    maps: Res<Assets<MapData>>,
+    mut query: Query<(&mut Transform, &PlayerStateMachine), With<Player>>,
+    mut change_map_ev: EventWriter<ChangeMap>,
+) {
+    if query.is_empty() || !input.just_pressed(Action::Up) {
+        return;
+    }
+    let Some(data) = maps.get(&map.0) else {
+        return;
+    };
+    let (mut transform, state) = query.single_mut();
+    if !state.current().on_ground() {
+        return;
+    }
+    let feet = Vec2::new(
+        transform.translation.x,
+        transform.translation.y - PLAYER_HALF_HEIGHT,
+    );
+    let Some(portal) = data.portal_at(feet) else {
+        return;
+    };
+    let Some(path) = map_path(&portal.to_map.to_string()) else {
+        warn!(
+            "portal {} target map {} not found",
+            portal.name, portal.to_map
+        );
+        return;
+    };
+    let current = asset_server.get_handle_path(&map.0);
+    if current.is_some_and(|current| current.path() == Path::new(&path)) {
+        //同一地图内的传送门,直接移动到目标传送门
+        if let Some(target) = data.portal(&portal.to_name) {
+            let position = target.position();
+            transform.translation.x = position.x;
+            transform.translation.y = position.y + PLAYER_HALF_HEIGHT;
         }
-        commands.entity(entity).remove::<Ground>();
-        commands.entity(entity).insert(Rise);
-
-        let dt = time.delta_seconds();
-        player.translation.y -= GRAVITY * dt * 2.0;
-        controller.translation = Some(Vec2::new(player.translation.x, player.translation.y));
-    } else if !input.pressed(KeyCode::AltLeft) {
-        if input.pressed(KeyCode::Right) {
-            player.translation.x = time.delta_seconds() * PLAYER_VELOCITY_X;
-        } else if input.pressed(KeyCode::Left) {
-            player.translation.x = time.delta_seconds() * PLAYER_VELOCITY_X * -1.0;
+        return;
+    }
+    change_map_ev.send(ChangeMap {
+        path,
+        spawn: SpawnPoint::Portal(portal.to_name.clone()),
+    });
+}
+
+//在梯子或绳子旁按上键抓住,站在顶端时按下键向下爬
+fn update_climb_start(
+    input: Res<Input<Action>>,
+    mut commands: Commands,
+    q_ladder: Query<&LadderRope>,
+    mut query: Query<(Entity, &mut Transform, &mut Player, &mut PlayerStateMachine)>,
+    mut state_ev: EventWriter<PlayerStateChanged>,
+) {
+    if query.is_empty() || input.pressed(Action::Jump) {
+        return;
+    }
+    let (entity, mut transform, mut player, mut state) = query.single_mut();
+    if !state.current().can_change(PlayerState::Climb) {
+        return;
+    }
+    let ground = state.current().on_ground();
+    let feet = Vec2::new(
+        transform.translation.x,
+        transform.translation.y - PLAYER_HALF_HEIGHT,
+    );
+    let ladder = if input.pressed(Action::Up) {
+        q_ladder.iter().find(|ladder| ladder.can_grab(feet))
+    } else if input.pressed(Action::Crouch) && ground {
+        q_ladder
+            .iter()
+            .find(|ladder| ladder.can_grab_from_top(feet))
+    } else {
+        None
+    };
+    let Some(ladder) = ladder else {
+        return;
+    };
+    transform.translation.x = ladder.x as f32;
+    //从顶端抓住时稍微向下,避免马上爬出顶端
+    if feet.y >= ladder.top() {
+        transform.translation.y = ladder.top() - 1.0 + PLAYER_HALF_HEIGHT;
+    }
+    player.velocity = Vect::ZERO;
+    commands.entity(entity).insert(Climb(ladder.clone()));
+    state_ev.send_batch(state.set(PlayerState::Climb));
+}
+
+//沿梯子或绳子上下移动,按跳跃键和左右键跳离
+fn update_climb(
+    input: Res<Input<Action>>,
+    time: Res<Time>,
+    config: Res<PhysicsConfig>,
+    mut commands: Commands,
+    mut query: Query<(
+        Entity,
+        &mut Transform,
+        &mut Player,
+        &mut PlayerStateMachine,
+        &Climb,
+    )>,
+    mut state_ev: EventWriter<PlayerStateChanged>,
+) {
+    if query.is_empty() {
+        return;
+    }
+    let (entity, mut transform, mut player, mut state, climb) = query.single_mut();
+    let ladder = &climb.0;
+    let dt = time.delta_seconds();
+
+    if input.pressed(Action::Jump)
+        && (input.pressed(Action::MoveLeft) || input.pressed(Action::MoveRight))
+    {
+        let direction = if input.pressed(Action::MoveRight) {
+            1.0
+        } else {
+            -1.0
+        };
+        //从梯子上跳下的高度为普通跳跃的一半
+        player.velocity = Vec2::new(direction * config.walk_speed, config.jump_force * 0.5);
+        commands.entity(entity).remove::<Climb>();
+        state_ev.send_batch(state.set(PlayerState::Jump));
+        return;
+    }
+
+    let mut feet = transform.translation.y - PLAYER_HALF_HEIGHT;
+    if input.pressed(Action::Up) {
+        feet += config.climb_speed * dt;
+    } else if input.pressed(Action::Crouch) {
+        feet -= config.climb_speed * dt;
+    }
+
+    if feet >= ladder.top() {
+        if ladder.uf != 0 {
+            //爬到顶端,落在上面的地砖
+            feet = ladder.top() + 1.0;
+            commands.entity(entity).remove::<Climb>();
+            state_ev.send_batch(state.set(PlayerState::Fall));
+        } else {
+            feet = ladder.top();
         }
+    } else if feet <= ladder.bottom() {
+        //爬到底端松手
+        commands.entity(entity).remove::<Climb>();
+        state_ev.send_batch(state.set(PlayerState::Fall));
+    }
+    transform.translation.y = feet + PLAYER_HALF_HEIGHT;
+}
+
+fn update_input(
+    input: Res<Input<Action>>,
+    time: Res<FixedTime>,
+    config: Res<PhysicsConfig>,
+    mut query: Query<(
+        &mut Player,
+        &mut PlayerStateMachine,
+        &mut KinematicCharacterController,
+    )>,
+    mut state_ev: EventWriter<PlayerStateChanged>,
+) {
+    if query.is_empty() {
+        return;
+    }
+    let (mut player, mut state, mut controller) = query.single_mut();
+    if !state.current().on_ground() {
+        return;
+    }
+    let dt = time.period.as_secs_f32();
+    let mut walk = 0.0;
+    if input.pressed(Action::MoveRight) {
+        walk = 1.0;
+    } else if input.pressed(Action::MoveLeft) {
+        walk = -1.0;
+    }
+    //跳跃后的位移在update_rise中计算
+    if input.pressed(Action::Jump) && input.pressed(Action::Crouch) {
+        player.velocity = Vec2::new(0.0, config.down_jump_force);
+        state_ev.send_batch(state.set(PlayerState::DownJump));
+    } else if input.pressed(Action::Jump) {
+        player.velocity = Vec2::new(walk * config.walk_speed, config.jump_force);
+        state_ev.send_batch(state.set(PlayerState::Jump));
+    } else {
+        let slope = player.foot_hold_type == FootHoldType::Slope;
+        player.velocity = Vec2::new(config.ground_speed(walk, slope), 0.0);
+        let next = if walk != 0.0 {
+            PlayerState::Walk
+        } else if input.pressed(Action::Crouch) {
+            PlayerState::Prone
+        } else {
+            PlayerState::Stand
+        };
+        state_ev.send_batch(state.set(next));
+
+        let x = player.velocity.x * dt;
         // println!("{:?}", player.foothold);
         match player.foot_hold_type {
-            FootHoldType::Slope => {
-                controller.translation = Some(Vec2::new(player.translation.x, -GRAVITY))
-            }
-            FootHoldType::Horizontal => {
-                controller.translation = Some(Vec2::new(player.translation.x, -MIN_FORCE))
-            }
-            FootHoldType::Vertical => {
-                controller.translation = Some(Vec2::new(player.translation.x, -MIN_FORCE))
-            }
+            FootHoldType::Slope => controller.translation = Some(Vec2::new(x, -SLOPE_FORCE)),
+            FootHoldType::Horizontal => controller.translation = Some(Vec2::new(x, -MIN_FORCE)),
+            FootHoldType::Vertical => controller.translation = Some(Vec2::new(x, -MIN_FORCE)),
             FootHoldType::Unknow => {
-                controller.translation = Some(Vec2::new(player.translation.x, -MIN_FORCE));
+                controller.translation = Some(Vec2::new(x, -MIN_FORCE));
             }
         }
     }
-    // player.translation = Some(translation);
 }
 
+//跳跃和下跳的上升过程
 fn update_rise(
-    time: Res<Time>,
-    mut commands: Commands,
-    mut query: Query<(Entity, &mut Player, &mut KinematicCharacterController), With<Rise>>,
+    time: Res<FixedTime>,
+    config: Res<PhysicsConfig>,
+    mut query: Query<(
+        &mut Player,
+        &mut PlayerStateMachine,
+        &mut KinematicCharacterController,
+    )>,
+    mut state_ev: EventWriter<PlayerStateChanged>,
 ) {
     if query.is_empty() {
         return;
     }
-    let (entity, mut player, mut controller) = query.single_mut();
-    let dt = time.delta_seconds();
-    player.translation.y -= GRAVITY * dt * 2.0;
+    let (mut player, mut state, mut controller) = query.single_mut();
+    if !matches!(state.current(), PlayerState::Jump | PlayerState::DownJump) {
+        return;
+    }
+    let dt = time.period.as_secs_f32();
 
     // println!("{:?}", player.foot_hold_type);
     if player.foot_hold_type == FootHoldType::Vertical {
-        player.translation.x = 0.0;
+        player.velocity.x = 0.0;
     }
-    controller.translation = Some(Vec2::new(player.translation.x, player.translation.y));
-    if player.translation.y <= 0.0 {
-        commands.entity(entity).insert(Fall);
-        commands.entity(entity).remove::<Rise>();
+    controller.translation = Some(config.integrate(&mut player.velocity, dt));
+    //下跳在update_downjump中结束
+    if state.current() == PlayerState::Jump && player.velocity.y <= 0.0 {
+        state_ev.send_batch(state.set(PlayerState::Fall));
     }
 }
 
 fn update_fall(
-    time: Res<Time>,
-    mut commands: Commands,
-    mut query: Query<(&mut Player, &mut KinematicCharacterController), With<Fall>>,
+    time: Res<FixedTime>,
+    config: Res<PhysicsConfig>,
+    mut query: Query<(
+        &mut Player,
+        &PlayerStateMachine,
+        &mut KinematicCharacterController,
+    )>,
 ) {
     if query.is_empty() {
         return;
     }
 
-    let (mut player, mut controller) = query.single_mut();
-    let dt = time.delta_seconds();
-    player.translation.y -= GRAVITY * dt * 2.0;
-    if player.foot_hold_type == FootHoldType::Vertical {
-        player.translation.x = 0.0;
+    let (mut player, state, mut controller) = query.single_mut();
+    if state.current() != PlayerState::Fall {
+        return;
     }
-    if player.translation.y < -MAX_FALL_SPEED {
-        player.translation.y = -MAX_FALL_SPEED;
+    let dt = time.period.as_secs_f32();
+    if player.foot_hold_type == FootHoldType::Vertical {
+        player.velocity.x = 0.0;
     }
     player.layer = -1;
     // println!("{}", player.translation.y);
-    controller.translation = Some(Vec2::new(player.translation.x, player.translation.y));
+    controller.translation = Some(config.integrate(&mut player.velocity, dt));
 
     // let mut group = CollisionGroups::new(Group::GROUP_1, Group::ALL);
     // if player.translation.x >= 0.0 {
@@ -376,112 +748,92 @@ fn update_layer(
     }
 }
 
+//状态切换时更换人物动作,两种物理后端共用
 fn update_player_animation(
-    input: Res<Input<KeyCode>>,
     mut commands: Commands,
-    mut query: Query<(
-        Entity,
-        &mut Animation,
-        &mut KinematicCharacterControllerOutput,
-        &mut Player,
-    )>,
-    assets: ResMut<AnimateAssets>,
-    mut state_change_ev: EventWriter<StateChangeEvent>,
+    mut state_ev: EventReader<PlayerStateChanged>,
+    query: Query<(Entity, &Animation, Option<&Climb>), With<Player>>,
+    assets: Res<AnimateAssets>,
 ) {
+    let Some(changed) = state_ev.iter().last() else {
+        return;
+    };
     if query.is_empty() {
         return;
     }
-    let (entity, mut animation, mut output, mut player) = query.single_mut();
-
-    if output.desired_translation.x.abs() > 0.0 && output.grounded {
-        //walk状态
-        if animation.name != "walk" {
-            commands
-                .entity(entity)
-                .insert(assets.animate_map.get("walk").unwrap().clone());
-            state_change_ev.send_default();
-        }
-        // println!("walk");
-    } else if output.desired_translation.x.abs() == 0.0 && output.grounded {
-        //stand状态或prone状态
-        if input.pressed(KeyCode::Down) {
-            if animation.name != "prone" {
-                commands
-                    .entity(entity)
-                    .insert(assets.animate_map.get("prone").unwrap().clone());
-                state_change_ev.send_default();
-            }
-        } else {
-            if animation.name != "stand" {
-                commands
-                    .entity(entity)
-                    .insert(assets.animate_map.get("stand").unwrap().clone());
-                state_change_ev.send_default();
-            }
-        }
-    } else {
-        //jump状态
-        if animation.name != "jump" {
-            commands
-                .entity(entity)
-                .insert(assets.animate_map.get("jump").unwrap().clone());
-            state_change_ev.send_default();
-        }
+    let (entity, animation, climb) = query.single();
+    let next = assets.get(changed.to.animation(climb));
+    if animation.name != next.name {
+        commands.entity(entity).insert(next.clone());
     }
-    // println!("{:?}", animation);
 }
 
 //处理下跳
 fn update_downjump(
-    mut commands: Commands,
-    time: Res<Time>,
-    mut query: Query<
-        (
-            Entity,
-            &mut DownJumpTimer,
-            &mut KinematicCharacterController,
-            &mut Player,
-        ),
-        With<Player>,
-    >,
+    config: Res<PhysicsConfig>,
+    mut query: Query<(&mut PlayerStateMachine, &mut KinematicCharacterController)>,
+    mut state_ev: EventWriter<PlayerStateChanged>,
 ) {
     if query.is_empty() {
         return;
     }
-    let (entity, mut timer, mut controller, mut player) = query.single_mut();
-    if timer.0.tick(time.delta()).just_finished() {
+    let (mut state, mut controller) = query.single_mut();
+    if state.current() != PlayerState::DownJump {
+        return;
+    }
+    if state.elapsed() >= config.down_jump_time {
         controller.filter_groups = Some(CollisionGroups::new(Group::GROUP_1, Group::ALL));
-        commands.entity(entity).remove::<DownJumpTimer>();
+        state_ev.send_batch(state.set(PlayerState::Fall));
     } else {
         controller.filter_groups = Some(CollisionGroups::new(Group::GROUP_5, Group::ALL));
     }
 }
 
 fn update_group(
-    mut commands: Commands,
-    mut query: Query<
-        (Entity, &mut KinematicCharacterController, &mut Player),
-        Without<DownJumpTimer>,
-    >,
+    graph: Option<Res<FootholdGraph>>,
+    mut query: Query<(
+        &mut KinematicCharacterController,
+        &Player,
+        &Transform,
+        &PlayerStateMachine,
+    )>,
 ) {
     if query.is_empty() {
         return;
     }
 
-    let (entity, mut controller, player) = query.single_mut();
-    let filter = FootHold::get_foothold_layer(player.layer);
+    let (mut controller, player, transform, state) = query.single_mut();
+    //下跳时的碰撞由update_downjump处理
+    if state.current() == PlayerState::DownJump {
+        return;
+    }
+    let ground = state.current().on_ground();
+    //在地面时只与相同layer的地砖碰撞
+    //在空中时与所有地板碰撞,但只与落点地砖相同layer的墙碰撞
+    let filter = if ground {
+        FootHold::get_foothold_layer(player.layer)
+    } else {
+        let feet = Vec2::new(
+            transform.translation.x,
+            transform.translation.y - PLAYER_HALF_HEIGHT,
+        );
+        match graph.and_then(|graph| FootHold::get_bottom_layer(&graph, feet)) {
+            Some((layer, _)) => FootHold::get_foothold_layer(layer) | FLOOR_GROUP,
+            None => Group::ALL,
+        }
+    };
     let mut group = CollisionGroups::new(Group::GROUP_1, filter);
-    if player.translation.y <= 0.0 {
+    if player.velocity.y <= 0.0 {
         group.memberships = Group::GROUP_1;
     }
-    if player.translation.y > 0.0 {
+    if player.velocity.y > 0.0 {
         group.memberships = Group::GROUP_2;
     }
 
-    if player.translation.x >= 0.0 {
+    if player.velocity.x >= 0.0 {
         group.memberships = group.memberships | Group::GROUP_3;
     }
-    if player.translation.x <= 0.0 {
+    if player.velocity.x <= 0.0 {
         group.memberships = group.memberships | Group::GROUP_4;
     }
 
@@ -515,7 +867,7 @@ fn update_print(mut query: Query<&mut KinematicCharacterController>) {
 }
 
 fn update_direction(
-    input: Res<Input<KeyCode>>,
+    input: Res<Input<Action>>,
     mut commands: Commands,
     query: Query<Entity, With<Player>>,
 ) {
@@ -523,28 +875,25 @@ fn update_direction(
         return;
     }
     let entity = query.single();
-    if input.pressed(KeyCode::Right) {
+    if input.pressed(Action::MoveRight) {
         commands.entity(entity).insert(Direction::Right);
-    } else if input.pressed(KeyCode::Left) {
+    } else if input.pressed(Action::MoveLeft) {
         commands.entity(entity).insert(Direction::Left);
     }
 }
 
 //通过碰撞检测人物是否在地面上
 pub fn update_ground(
-    mut commands: Commands,
-    mut query: Query<(Entity, &mut KinematicCharacterControllerOutput, &mut Player), With<Fall>>,
+    mut query: Query<(&KinematicCharacterControllerOutput, &mut PlayerStateMachine)>,
+    mut state_ev: EventWriter<PlayerStateChanged>,
 ) {
     if query.is_empty() {
         return;
     }
-    let (mut entity, mut output, mut player) = query.single_mut();
+    let (output, mut state) = query.single_mut();
 
-    if output.grounded {
-        commands.entity(entity).insert(Ground);
-        commands.entity(entity).remove::<Fall>();
-    } else {
-        commands.entity(entity).remove::<Ground>();
+    if state.current() == PlayerState::Fall && output.grounded {
+        state_ev.send_batch(state.set(PlayerState::Stand));
     }
 }
 
@@ -573,17 +922,27 @@ pub fn update_collision(
 
 //检测人物是否走到fh边缘并下落
 pub fn update_edge(
-    mut commands: Commands,
-    mut query: Query<(Entity, &mut KinematicCharacterControllerOutput, &mut Player), With<Ground>>,
+    mut query: Query<(
+        &KinematicCharacterControllerOutput,
+        &mut Player,
+        &mut PlayerStateMachine,
+    )>,
+    mut state_ev: EventWriter<PlayerStateChanged>,
 ) {
     if query.is_empty() {
         return;
     }
-    let (mut entity, mut output, mut player) = query.single_mut();
-    if !output.grounded {
-        player.translation.y = 0.0;
-        commands.entity(entity).remove::<Ground>();
-        commands.entity(entity).insert(Rise);
+    let (output, mut player, mut state) = query.single_mut();
+    if state.current().on_ground() && !output.grounded {
+        player.velocity.y = 0.0;
+        state_ev.send_batch(state.set(PlayerState::Fall));
+    }
+}
+
+//记录人物在当前状态停留的时间
+fn update_state_time(time: Res<FixedTime>, mut query: Query<&mut PlayerStateMachine>) {
+    for mut state in query.iter_mut() {
+        state.tick(time.period.as_secs_f32());
     }
 }
 
@@ -613,31 +972,107 @@ fn setup_player_assets(
     asset_server: Res<AssetServer>,
     mut next_state: ResMut<NextState<Load>>,
 ) {
-    let mut prone: Vec<Handle<Image>> = Vec::new();
-    prone.push(asset_server.load("prone0.png"));
+    commands.insert_resource(PlayerAssets {
+        definition: asset_server.load(PLAYER_ANIMATION),
+        handle_map: HashMap::new(),
+    });
+    next_state.set(Load::Loading);
+}
 
-    let mut walk: Vec<Handle<Image>> = Vec::new();
-    walk.push(asset_server.load("walk0.png"));
-    walk.push(asset_server.load("walk1.png"));
-    walk.push(asset_server.load("walk2.png"));
-    walk.push(asset_server.load("walk3.png"));
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::animate::LoopMode;
 
-    let mut stand: Vec<Handle<Image>> = Vec::new();
-    stand.push(asset_server.load("stand0.png"));
-    stand.push(asset_server.load("stand1.png"));
-    stand.push(asset_server.load("stand2.png"));
+    fn ladder_rope(l: i32) -> LadderRope {
+        LadderRope {
+            id: 1,
+            l,
+            uf: 1,
+            x: 0,
+            y1: -100,
+            y2: 0,
+            page: 0,
+        }
+    }
 
-    let mut jump: Vec<Handle<Image>> = Vec::new();
-    jump.push(asset_server.load("jump0.png"));
+    fn animate_assets(names: &[&str]) -> AnimateAssets {
+        let animate_map = names
+            .iter()
+            .map(|name| {
+                let animation =
+                    Animation::new(name, vec![0], vec![0.1], vec![Vec2::ZERO], LoopMode::Loop);
+                (name.to_string(), animation)
+            })
+            .collect();
+        AnimateAssets::new(animate_map).unwrap()
+    }
 
-    let mut handle_map = HashMap::new();
-    handle_map.insert("prone".to_string(), prone);
-    handle_map.insert("walk".to_string(), walk);
-    handle_map.insert("stand".to_string(), stand);
-    handle_map.insert("jump".to_string(), jump);
+    #[test]
+    fn climbing_uses_ladder_and_rope_actions() {
+        let ladder = PlayerState::Climb.animation(Some(&Climb(ladder_rope(1))));
+        let rope = PlayerState::Climb.animation(Some(&Climb(ladder_rope(0))));
+        assert_eq!((ladder, rope), ("ladder", "rope"));
 
-    commands.insert_resource(PlayerAssets {
-        handle_map: handle_map,
-    });
-    next_state.set(Load::Loading);
+        //默认定义没有ladder和rope,用jump代替
+        let assets = animate_assets(&["stand1", "walk1", "jump", "prone"]);
+        assert_eq!(assets.get(ladder).name, "jump");
+        assert_eq!(assets.get(rope).name, "jump");
+
+        //定义了ladder和rope后直接使用
+        let assets = animate_assets(&["stand1", "jump", "ladder", "rope"]);
+        assert_eq!(assets.get(ladder).name, "ladder");
+        assert_eq!(assets.get(rope).name, "rope");
+    }
+
+    #[test]
+    fn missing_jump_falls_back_to_first_action() {
+        let assets = animate_assets(&["walk1", "stand1"]);
+        assert_eq!(assets.get("jump").name, "stand1");
+        assert!(AnimateAssets::new(HashMap::new()).is_none());
+    }
+
+    //动作定义读取失败时只输出一次错误,之后不再检查
+    #[test]
+    fn missing_definition_moves_to_failed() {
+        let mut app = App::new();
+        app.add_plugins((MinimalPlugins, AssetPlugin::default()))
+            .add_asset::<AnimationSet>()
+            .init_asset_loader::<crate::animate::AnimationSetLoader>()
+            .add_state::<Load>()
+            .add_systems(Update, check_textures.run_if(in_state(Load::Loading)));
+        let definition = app
+            .world
+            .resource::<AssetServer>()
+            .load("missing.animation.json");
+        app.insert_resource(PlayerAssets {
+            definition,
+            handle_map: HashMap::new(),
+        });
+        app.world
+            .resource_mut::<NextState<Load>>()
+            .set(Load::Loading);
+        for _ in 0..200 {
+            app.update();
+            if *app.world.resource::<State<Load>>().get() != Load::Loading {
+                break;
+            }
+            std::thread::sleep(std::time::Duration::from_millis(5));
+        }
+        assert_eq!(*app.world.resource::<State<Load>>().get(), Load::Failed);
+    }
+
+    //默认定义中的图片都要存在,否则该动作加载失败
+    #[test]
+    fn shipped_actions_have_images() {
+        let bytes = std::fs::read(Path::new("assets").join(PLAYER_ANIMATION)).unwrap();
+        let set: AnimationSet = serde_json::from_slice(&bytes).unwrap();
+        for (name, action) in &set.actions {
+            for frame in &action.frames {
+                let image = Path::new("assets").join(&frame.image);
+                assert!(image.is_file(), "{} uses missing {:?}", name, image);
+            }
+        }
+        assert!(!set.actions.contains_key("ladder") && !set.actions.contains_key("rope"));
+    }
 }
@@ -1,4 +1,7 @@
-use std::cmp::{max, min};
+use std::{
+    cmp::{max, min},
+    env,
+};
 
 pub fn composite_zindex(z: i128, z0: i128, z1: i128, z2: i128) -> f32 {
     let scale = 16; // 1024
@@ -23,3 +26,19 @@ pub fn cal_ay(oy: f32, height: f32) -> f32 {
     let y = (oy - height / 2.0) / height;
     return y;
 }
+
+//读取命令行参数 --name value 或 --name=value,没有时读取环境变量
+pub fn arg_or_env(name: &str, var: &str) -> Option<String> {
+    let flag = format!("--{}", name);
+    let prefix = format!("--{}=", name);
+    let mut args = env::args().skip(1);
+    let mut value = None;
+    while let Some(arg) = args.next() {
+        if arg == flag {
+            value = args.next();
+        } else if let Some(v) = arg.strip_prefix(&prefix) {
+            value = Some(v.to_string());
+        }
+    }
+    value.or_else(|| env::var(var).ok())
+}